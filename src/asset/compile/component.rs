@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use serialize::json::Json;
+use scene::{Scene, Entity};
+use uuid::Uuid;
+use super::error::CompileError;
+use super::transform::TransformCompiler;
+
+/// A component in a .scene file, and where it was found.
+pub struct ComponentSource<'a> {
+    pub json: &'a Json,
+    /// Entity the component belongs to.
+    pub entity: Entity,
+    /// UUID of the entity, as written in the file.
+    pub id: Option<&'a str>,
+    /// Index of the component within its entity.
+    pub index: usize,
+    /// UUID of the entity this one is nested in, if any.
+    pub nested_in: Option<&'a str>,
+}
+
+impl<'a> ComponentSource<'a> {
+    /// An error in a field of the component.
+    pub fn error(&self, field: &str, message: String) -> CompileError {
+        self.locate(CompileError::new(message), field)
+    }
+
+    /// A warning about a field of the component.
+    pub fn warning(&self, field: &str, message: String) -> CompileError {
+        self.locate(CompileError::warning(message), field)
+    }
+
+    /// An error for every key of the component that isn't "type" or in
+    /// `known`.
+    pub fn check_keys(&self, known: &[&str]) -> Vec<CompileError> {
+        match self.json.as_object() {
+            Some(object) => object.keys()
+                .filter(|x| &x[..] != "type" && !known.contains(&&x[..]))
+                .map(|x| self.error(x, "unknown field".to_string()))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn locate(&self, mut error: CompileError, field: &str) -> CompileError {
+        error.entity = self.id.map(|x| x.to_string());
+        error.component = Some(self.index);
+        error.field = Some(field.to_string());
+        error
+    }
+}
+
+/// Compiles one type of component from .scene files into its system.
+///
+/// # Description
+///
+/// For every scene, compile_scene() calls prepare(), then compile() for each
+/// component whose "type" is type_name(), and finish() once every component
+/// of the scene is compiled. Components can refer to entities further down
+/// the file, so references are best resolved in finish().
+///
+/// compile() and finish() return every problem they find rather than stopping
+/// at the first, so that one bad field doesn't hide the others. The position
+/// in the file is filled in from the entity, component and field of each.
+/// Nothing is written if any of them is an error.
+pub trait ComponentCompiler {
+    /// The "type" of the components this compiles. Must be unique.
+    fn type_name(&self) -> &'static str;
+
+    /// Gets ready for a new scene, e.g. by registering the system the
+    /// components go into and forgetting the state of the last scene.
+    fn prepare(&mut self, _scene: &mut Scene) { }
+
+    /// `uuids` maps the UUID of every entity in the file to the entity created
+    /// for it.
+    fn compile(&mut self, scene: &mut Scene, uuids: &HashMap<Uuid, Entity>, source: &ComponentSource)
+    -> Vec<CompileError>;
+
+    fn finish(&mut self, _scene: &mut Scene, _uuids: &HashMap<Uuid, Entity>) -> Vec<CompileError> {
+        Vec::new()
+    }
+}
+
+/// Compiles one entry of the "resources" section of .scene files.
+///
+/// # Description
+///
+/// compile() reads the value under name() and inserts the resource into the
+/// scene. Only resources registered with Scene::register_saved_resource() are
+/// written, so compile() has to register the type as well.
+///
+/// ```ignore
+/// fn compile(&mut self, scene: &mut Scene, json: &Json) -> Result<(), String> {
+///     let gravity = try!(json.as_f64().ok_or("expected a number".to_string()));
+///     scene.register_saved_resource::<Gravity>();
+///     scene.insert_resource(Gravity(gravity as f32));
+///     Ok(())
+/// }
+/// ```
+pub trait ResourceCompiler {
+    /// The key of the resource under "resources". Must be unique.
+    fn name(&self) -> &'static str;
+
+    fn compile(&mut self, scene: &mut Scene, json: &Json) -> Result<(), String>;
+}
+
+/// The component compilers that compile_scene() dispatches to by type, and
+/// the resource compilers it dispatches to by name.
+pub struct ComponentCompilers {
+    compilers: Vec<Box<ComponentCompiler>>,
+    resources: Vec<Box<ResourceCompiler>>,
+}
+
+impl ComponentCompilers {
+    /// Creates a registry with the compilers for the engine's own components.
+    pub fn new() -> ComponentCompilers {
+        let mut compilers = ComponentCompilers { compilers: Vec::new(), resources: Vec::new() };
+        compilers.register(Box::new(TransformCompiler::new()));
+        compilers
+    }
+
+    /// Adds a compiler. Panics if there already is one for the same type.
+    pub fn register(&mut self, compiler: Box<ComponentCompiler>) {
+        let name = compiler.type_name();
+        assert!(self.get_mut(name).is_none(),
+            "A compiler for {} components is already registered.", name);
+        self.compilers.push(compiler);
+    }
+
+    /// Adds a resource compiler. Panics if there already is one for the same
+    /// name.
+    pub fn register_resource(&mut self, compiler: Box<ResourceCompiler>) {
+        let name = compiler.name();
+        assert!(self.get_resource_mut(name).is_none(),
+            "A compiler for the {} resource is already registered.", name);
+        self.resources.push(compiler);
+    }
+
+    pub fn get_resource_mut(&mut self, name: &str) -> Option<&mut ResourceCompiler> {
+        self.resources.iter_mut()
+            .find(|x| x.name() == name)
+            .map(|x| &mut **x)
+    }
+
+    pub fn get_mut(&mut self, type_name: &str) -> Option<&mut ComponentCompiler> {
+        self.compilers.iter_mut()
+            .find(|x| x.type_name() == type_name)
+            .map(|x| &mut **x)
+    }
+
+    pub fn prepare(&mut self, scene: &mut Scene) {
+        for compiler in self.compilers.iter_mut() {
+            compiler.prepare(scene);
+        }
+    }
+
+    pub fn finish(&mut self, scene: &mut Scene, uuids: &HashMap<Uuid, Entity>) -> Vec<CompileError> {
+        let mut errors = Vec::new();
+        for compiler in self.compilers.iter_mut() {
+            errors.extend(compiler.finish(scene, uuids).into_iter());
+        }
+        errors
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+
+/// How bad a CompileError is.
+#[derive(Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// The asset can't be compiled.
+    Error,
+    /// The asset compiles, but something was skipped or looks wrong.
+    Warning,
+}
+
+/// A problem found while compiling an asset.
+///
+/// # Description
+///
+/// Everything but the severity and message is optional, and is filled in as
+/// far as the compiler knows it. Displays like a compiler message, with
+/// warnings marked after the position:
+///
+/// ```text
+/// levels/test.scene:12:23: entity 6b255092-90b5-42fe-a751-144b27d9870d,
+/// component 0, "position": expected 3 numbers, found 2
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct CompileError {
+    pub severity: Severity,
+    pub path: Option<String>,
+    /// Line and column, both starting at 1.
+    pub position: Option<(usize, usize)>,
+    /// UUID of the entity, as written in the file.
+    pub entity: Option<String>,
+    /// Index of the component within its entity.
+    pub component: Option<usize>,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(message: String) -> CompileError {
+        CompileError {
+            severity: Severity::Error,
+            path: None,
+            position: None,
+            entity: None,
+            component: None,
+            field: None,
+            message: message,
+        }
+    }
+
+    pub fn warning(message: String) -> CompileError {
+        CompileError { severity: Severity::Warning, ..CompileError::new(message) }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref path) = self.path {
+            try!(write!(f, "{}:", path));
+        }
+        if let Some((line, column)) = self.position {
+            try!(write!(f, "{}:{}:", line, column));
+        }
+        if self.path.is_some() || self.position.is_some() {
+            try!(write!(f, " "));
+        }
+        if self.severity == Severity::Warning {
+            try!(write!(f, "warning: "));
+        }
+
+        let mut context = Vec::new();
+        if let Some(ref entity) = self.entity {
+            context.push(format!("entity {}", entity));
+        }
+        if let Some(component) = self.component {
+            context.push(format!("component {}", component));
+        }
+        if let Some(ref field) = self.field {
+            context.push(format!("\"{}\"", field));
+        }
+        if !context.is_empty() {
+            try!(write!(f, "{}: ", context.connect(", ")));
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+#[test]
+fn compile_error_display_test() {
+    let mut error = CompileError::new("expected 3 numbers, found 2".to_string());
+    assert_eq!(error.to_string(), "expected 3 numbers, found 2");
+
+    error.path = Some("test.scene".to_string());
+    error.position = Some((12, 23));
+    error.entity = Some("6b255092-90b5-42fe-a751-144b27d9870d".to_string());
+    error.component = Some(0);
+    error.field = Some("position".to_string());
+    assert_eq!(error.to_string(), "test.scene:12:23: entity 6b255092-90b5-42fe-a751-144b27d9870d, \
+        component 0, \"position\": expected 3 numbers, found 2");
+
+    let mut warning = CompileError::warning("unknown component type \"light\"".to_string());
+    warning.position = Some((3, 7));
+    assert_eq!(warning.to_string(), "3:7: warning: unknown component type \"light\"");
+}
@@ -1,32 +1,77 @@
 use std::old_path::Path;
-use std::old_io::{File, FileMode, FileAccess, FilePermission};
+use std::old_io::{self, File, FileMode, FileAccess, FilePermission};
 use std::old_io::fs::{self, PathExtensions};
 
+pub use self::component::{ComponentCompiler, ComponentCompilers, ComponentSource, ResourceCompiler};
+pub use self::error::{CompileError, Severity};
+
+mod component;
+mod error;
 mod scene;
+mod transform;
+mod value;
 
 
-pub fn compile_path(path: &Path, output_folder: &Path) {
+/// Compiles an asset, or every asset in a folder. Scene components are
+/// compiled with `compilers`.
+pub fn compile_path(path: &Path, output_folder: &Path, compilers: &mut ComponentCompilers) {
     if path.is_dir() {
-        let contents = fs::readdir(path).ok().unwrap();
+        let contents = match fs::readdir(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                report(path, vec![CompileError::new(format!("unable to read the folder: {}", e))]);
+                return;
+            }
+        };
         for entry in contents.iter() {
             if entry.is_dir() {
-                compile_path(entry, output_folder);
+                compile_path(entry, output_folder, compilers);
             } else {
-                compile_asset(entry, output_folder);
+                compile_asset(entry, output_folder, compilers);
             }
         }
     }
     else if path.is_file() {
-        compile_asset(path, output_folder);
+        compile_asset(path, output_folder, compilers);
     }
 }
 
-pub fn compile_asset(path: &Path, output_folder: &Path) {
-    let ext = path.extension_str()
-        .expect("No extension. Cannot determine file type.");
+pub fn compile_asset(path: &Path, output_folder: &Path, compilers: &mut ComponentCompilers) {
+    let ext = match path.extension_str() {
+        Some(ext) => ext,
+        None => {
+            report(path, vec![CompileError::warning(
+                "no extension to tell the asset type by, skipped".to_string())]);
+            return;
+        }
+    };
+
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            report(path, vec![CompileError::new(format!("unable to open the file: {}", e))]);
+            let _ = writeln!(&mut old_io::stderr(), "{} failed to compile.", path.display());
+            return;
+        }
+    };
 
-    let mut file = File::open(path)
-        .ok().expect("Unable to open file.");
+    //Compile the file based on extension. Everything goes to memory first so
+    //that a broken asset doesn't leave a half written output file behind.
+    let mut compiled: Vec<u8> = Vec::new();
+    let result = match ext {
+        "scene" => scene::compile_scene(&mut file, &mut compiled, compilers),
+        _ => return,
+    };
+    match result {
+        Ok(warnings) => report(path, warnings),
+        Err(errors) => {
+            let count = errors.iter().filter(|x| x.severity == Severity::Error).count();
+            report(path, errors);
+            let _ = writeln!(&mut old_io::stderr(), "{} failed to compile with {} error(s).",
+                path.display(), count);
+            return;
+        }
+    }
 
     //Output is relative to output_folder
     let mut output_path = output_folder.clone();
@@ -34,14 +79,22 @@ pub fn compile_asset(path: &Path, output_folder: &Path) {
     output_path.set_extension(get_compiled_extension(ext));
     
     //Create directories + output file
-    fs::mkdir_recursive(&output_path.dir_path(), FilePermission::all());
-    let mut output_file = File::create(&output_path)
-        .ok().expect("Unable to open output file.");
+    let written = fs::mkdir_recursive(&output_path.dir_path(), FilePermission::all())
+        .and_then(|_| File::create(&output_path))
+        .and_then(|mut output_file| output_file.write_all(&compiled));
+    if let Err(e) = written {
+        report(path, vec![CompileError::new(
+            format!("unable to write {}: {}", output_path.display(), e))]);
+        let _ = writeln!(&mut old_io::stderr(), "{} failed to compile.", path.display());
+    }
+}
 
-    //Compile the file based on extension
-    match ext {
-        "scene" => scene::compile_scene(&mut file, &mut output_file),
-        _ => { }
+/// Prints the errors and warnings found in an asset to stderr.
+fn report(path: &Path, mut errors: Vec<CompileError>) {
+    let mut stderr = old_io::stderr();
+    for error in errors.iter_mut() {
+        error.path = Some(path.display().to_string());
+        let _ = writeln!(&mut stderr, "{}", error);
     }
 }
 
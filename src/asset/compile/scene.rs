@@ -1,23 +1,62 @@
 use std::collections::HashMap;
-use serialize::json::{self, Json};
-use cgmath::{Vector3, Quaternion};
+use serialize::json::{self, Json, ParserError};
 use scene::{Scene, Entity};
 use uuid::Uuid;
+use super::{CompileError, Severity};
+use super::component::{ComponentCompilers, ComponentSource};
 
+/// The .scene format version written by the editor. Older files are brought
+/// up to date by MIGRATIONS before they are read.
+const VERSION: u64 = 0;
 
-pub fn compile_scene(input: &mut Reader, output: &mut Writer) {
-    let root = json::from_reader(input).ok().unwrap();
+/// Entry `n` migrates a file from version `n` to `n + 1`. Bump VERSION along
+/// with adding one.
+static MIGRATIONS: &'static [fn(&mut Json)] = &[];
 
-    let entities = root["entities"].as_array().unwrap();
 
-    //The first value written to the file is the number of entities to create.
-    //When we load the file, we create all the entities at once and store them
-    //in an array for easy access (since entities reference each other by ID in
-    //the compiled format).
-    output.write_le_u32(entities.len() as u32);
+/// Compiles a .scene file into the binary format loaded by Scene::load().
+///
+/// Components are compiled by the compiler registered for their type.
+/// Keeps going after errors to report as many as possible. Nothing is
+/// written to `output` unless the whole scene compiled. Returns the warnings
+/// on success, and the errors along with the warnings on failure.
+pub fn compile_scene(input: &mut Reader, output: &mut Writer, compilers: &mut ComponentCompilers)
+-> Result<Vec<CompileError>, Vec<CompileError>> {
+    let source = match input.read_to_string() {
+        Ok(source) => source,
+        Err(e) => return Err(vec![CompileError::new(format!("unable to read the file: {}", e))]),
+    };
+    let mut root = match Json::from_str(&source) {
+        Ok(root) => root,
+        Err(ParserError::SyntaxError(code, line, column)) => {
+            let mut error = CompileError::new(json::error_str(code).to_string());
+            error.position = Some((line, column));
+            return Err(vec![error]);
+        }
+        Err(ParserError::IoError(_, message)) => {
+            return Err(vec![CompileError::new(message.to_string())]);
+        }
+    };
+
+    //Bring older files up to date before reading them
+    if let Err(error) = migrate(&mut root, VERSION, MIGRATIONS) {
+        return Err(vec![error]);
+    }
+
+    let mut diag = Diagnostics { source: &source, errors: Vec::new() };
+    diag.check_keys(&root, &["version", "entities", "resources"], None, None);
+
+    //Entities can be nested in the "children" of other entities. Flatten them
+    //depth first so every entity gets an ID, remembering where each one was
+    //nested.
+    let mut entities = Vec::new();
+    match root.find("entities").and_then(|x| x.as_array()) {
+        Some(list) => flatten_entities(&mut diag, list, None, &mut entities),
+        None => diag.error(None, None, Some("entities"), "expected an array of entities".to_string()),
+    }
 
-    //For now, during the compilation step, we'll keep a map of UUIDs to IDs.
-    //The IDs count up starting from 0, so the map would look like this:
+    //For now, during the compilation step, we'll keep a map of UUIDs to
+    //entities. The IDs count up starting from 0, so the map would look like:
     //  70a44f30-e3e6-45d3-a266-8afd8652f9a0  =>  0
     //  f2c23206-83a4-40c3-aa7d-a903f4bdcbbc  =>  1
     //  65840c45-62ce-46de-870b-7ad304c882d1  =>  2
@@ -29,75 +68,423 @@ pub fn compile_scene(input: &mut Reader, output: &mut Writer) {
     //to the systems and let them write it in the format that makes the most
     //sense for fast loading (usually just a direct memory dump).
     let mut scene = Scene::new();
+    compilers.prepare(&mut scene);
 
 
     //First pass, create UUID map.
-    for entity in entities.iter() {
-        let uuid_str = entity["id"].as_string().unwrap();
-        let uuid = Uuid::parse_str(uuid_str).ok().unwrap();
-
+    for (idx, entity) in entities.iter().enumerate() {
         //ASSUMPTION: From an empty EntityManager, sequential create() calls
         //result in Entities with sequential ids starting from 0. Should be
         //upheld by the sequential_test() unit test.
         let en = scene.entity_manager.create();
-        uuid_map.insert(uuid, en.id);
+
+        if let Some(id) = entity.id {
+            match Uuid::parse_str(id) {
+                Ok(uuid) => {
+                    if uuid_map.insert(uuid, en).is_some() {
+                        diag.error(Some(id), None, Some("id"),
+                            format!("entity number {} has the same ID as an earlier entity", idx + 1));
+                    }
+                }
+                Err(_) => diag.error(Some(id), None, Some("id"), "not a valid UUID".to_string()),
+            }
+        }
     }
 
 
     //Second pass, create components.
-    let mut idx = 0;
-    for entity in entities.iter() {
+    for (idx, entity) in entities.iter().enumerate() {
         //Based on the ASSUMPTION above
-        let en = Entity::new(idx, 0);
-        idx += 1;
+        let en = Entity::new(idx as u32, 0);
 
+        let components = match entity.json.find("components").and_then(|x| x.as_array()) {
+            Some(components) => components,
+            None => {
+                diag.error(entity.id, None, Some("components"), "expected an array of components".to_string());
+                continue;
+            }
+        };
 
-        let components = entity["components"].as_array().unwrap();
-        for comp in components.iter() {
+        //An entity has at most one component of each type
+        let mut types = Vec::new();
+        for (comp_idx, comp) in components.iter().enumerate() {
             //Match on component type string
-            let type_ = comp["type"].as_string().unwrap();
-            match type_ {
-                "transform" => {
-                    let ref mut sys = scene.transform_system;
+            let type_ = match comp.find("type").and_then(|x| x.as_string()) {
+                Some(type_) => type_,
+                None => {
+                    diag.error(entity.id, Some(comp_idx), Some("type"), "expected a string".to_string());
+                    continue;
+                }
+            };
+            if types.contains(&type_) {
+                diag.error(entity.id, Some(comp_idx), Some("type"),
+                    format!("entity already has a {} component", type_));
+                continue;
+            }
+            types.push(type_);
+
+            let source = ComponentSource {
+                json: comp,
+                entity: en,
+                id: entity.id,
+                index: comp_idx,
+                nested_in: entity.nested_in,
+            };
+            match compilers.get_mut(type_) {
+                Some(compiler) => {
+                    for error in compiler.compile(&mut scene, &uuid_map, &source).into_iter() {
+                        diag.add(error);
+                    }
+                }
+                None => diag.warning(entity.id, Some(comp_idx), Some("type"),
+                    format!("unknown component type \"{}\", skipped", type_)),
+            }
+        }
+    }
+
 
-                    //Create component if needed
-                    let inst = sys.create_or_get_instance(en);
+    //Third pass, let the compilers resolve references between entities
+    for error in compilers.finish(&mut scene, &uuid_map).into_iter() {
+        diag.add(error);
+    }
 
-                    sys.set_local_position(inst, parse_vector3(&comp["position"]));
-                    sys.set_local_rotation(inst, parse_quaternion(&comp["rotation"]));
-                    sys.set_local_scale(inst, comp["scale"].as_f64().unwrap() as f32);
+
+    //Scene-wide resources, each compiled by the compiler for its name
+    if let Some(resources) = root.find("resources") {
+        match resources.as_object() {
+            Some(resources) => {
+                for (name, json) in resources.iter() {
+                    match compilers.get_resource_mut(name) {
+                        Some(compiler) => {
+                            if let Err(message) = compiler.compile(&mut scene, json) {
+                                diag.resource(CompileError::new(message), name);
+                            }
+                        }
+                        None => diag.resource(CompileError::warning(
+                            "unknown resource, skipped".to_string()), name),
+                    }
                 }
-                _ => panic!("Unknown component type.")
             }
+            None => diag.error(None, None, Some("resources"), "expected an object of resources".to_string()),
         }
     }
 
+
+    if diag.errors.iter().any(|x| x.severity == Severity::Error) {
+        return Err(diag.errors);
+    }
+
+    //Scene::save() writes the format version and then the number of entities
+    //to create, one per entity in the file. When we load the file, we create
+    //all the entities at once and store them in an array for easy access
+    //(since entities reference each other by ID in the compiled format).
     scene.save(output);
+    Ok(diag.errors)
+}
+
+/// Collects the errors and warnings found while compiling a scene.
+///
+/// Entities are named by their UUID as written in the file. The position in
+/// the file is filled in from those.
+struct Diagnostics<'a> {
+    source: &'a str,
+    errors: Vec<CompileError>,
+}
+
+impl<'a> Diagnostics<'a> {
+    fn error(&mut self, entity: Option<&str>, component: Option<usize>, field: Option<&str>,
+    message: String) {
+        let error = CompileError::new(message);
+        self.push(error, entity, component, field);
+    }
+
+    fn warning(&mut self, entity: Option<&str>, component: Option<usize>, field: Option<&str>,
+    message: String) {
+        let warning = CompileError::warning(message);
+        self.push(warning, entity, component, field);
+    }
+
+    /// Flags every key of an object that isn't in `known`, to catch
+    /// misspelled fields and leftovers from bad merges.
+    fn check_keys(&mut self, json: &Json, known: &[&str], entity: Option<&str>,
+    component: Option<usize>) {
+        if let Some(object) = json.as_object() {
+            for key in object.keys() {
+                if !known.contains(&&key[..]) {
+                    self.error(entity, component, Some(key), "unknown field".to_string());
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, mut error: CompileError, entity: Option<&str>, component: Option<usize>,
+    field: Option<&str>) {
+        error.entity = entity.map(|x| x.to_string());
+        error.component = component;
+        error.field = field.map(|x| x.to_string());
+        self.add(error);
+    }
+
+    /// Adds an error about the resource `name`.
+    fn resource(&mut self, mut error: CompileError, name: &str) {
+        error.position = locate_resource(self.source, name);
+        error.field = Some(name.to_string());
+        self.errors.push(error);
+    }
+
+    /// Adds an error from a component compiler, finding its position from the
+    /// entity, component and field it names.
+    fn add(&mut self, mut error: CompileError) {
+        error.position = match error.entity {
+            Some(ref entity) => {
+                let field = error.field.as_ref().map(|x| &x[..]);
+                locate(self.source, entity, error.component, field)
+            }
+            None => None,
+        };
+        self.errors.push(error);
+    }
+}
+
+/// Finds the line and column of an entity's ID in the source, narrowed down
+/// to the component and field when they are given and can be found inside
+/// the entity.
+///
+/// JSON values don't remember where they came from, so this walks the source
+/// again. It is only called on source that parsed.
+fn locate(source: &str, entity: &str, component: Option<usize>, field: Option<&str>)
+-> Option<(usize, usize)> {
+    let mut offset = match find_id(source, entity) {
+        Some(offset) => offset,
+        None => return None,
+    };
+    //The "id" key sits directly in the entity object
+    let mut object = match enclosing_object(source, offset) {
+        Some(object) => object,
+        None => return Some(line_column(source, offset)),
+    };
+
+    if let Some(component) = component {
+        let element = find_key(source, object, "components")
+            .and_then(|(_, array)| nth_element(source, array, component));
+        match element {
+            Some(element) => {
+                offset = element;
+                object = element;
+            }
+            None => return Some(line_column(source, offset)),
+        }
+    }
+    if let Some(field) = field {
+        if let Some((key, _)) = find_key(source, object, field) {
+            offset = key;
+        }
+    }
+
+    Some(line_column(source, offset))
 }
 
-fn parse_vector3(json: &Json) -> Vector3<f32> {
-    let comps: Vec<&str> = json.as_string().unwrap().split_str(" ").collect();
-    assert!(comps.len() == 3);
+/// Finds the line and column of a key in the "resources" section.
+fn locate_resource(source: &str, name: &str) -> Option<(usize, usize)> {
+    source.find('{')
+        .and_then(|root| find_key(source, root, "resources"))
+        .and_then(|(_, resources)| find_key(source, resources, name))
+        .map(|(key, _)| line_column(source, key))
+}
 
-    Vector3::new(
-        comps[0].parse().unwrap(),
-        comps[1].parse().unwrap(),
-        comps[2].parse().unwrap()
-    )
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.chars().filter(|x| *x == '\n').count() + 1;
+    let column = offset - before.rfind('\n').map(|x| x + 1).unwrap_or(0) + 1;
+    (line, column)
 }
 
-fn parse_quaternion(json: &Json) -> Quaternion<f32> {
-    let comps: Vec<&str> = json.as_string().unwrap().split_str(" ").collect();
-    assert!(comps.len() == 4);
+/// Finds the opening brace of the innermost object around `offset`.
+fn enclosing_object(source: &str, offset: usize) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut open = Vec::new();
+    let mut i = 0;
+    while i < offset {
+        match bytes[i] {
+            b'"' => { i = skip_string(bytes, i); continue; }
+            b'{' | b'[' => open.push(i),
+            b'}' | b']' => { open.pop(); }
+            _ => (),
+        }
+        i += 1;
+    }
+    open.pop().and_then(|x| if bytes[x] == b'{' { Some(x) } else { None })
+}
 
-    Quaternion::new(
-        comps[0].parse().unwrap(),
-        comps[1].parse().unwrap(),
-        comps[2].parse().unwrap(),
-        comps[3].parse().unwrap()
-    )
+/// Finds a key of the object opening at `object`, without looking into the
+/// values of other keys. Returns where the key and its value start.
+fn find_key(source: &str, object: usize, key: &str) -> Option<(usize, usize)> {
+    let bytes = source.as_bytes();
+    let mut i = skip_whitespace(bytes, object + 1);
+    while i < bytes.len() && bytes[i] == b'"' {
+        let key_start = i;
+        i = skip_string(bytes, i);
+        let name = &source[key_start + 1..i - 1];
+        i = skip_whitespace(bytes, i);
+        if i >= bytes.len() || bytes[i] != b':' {
+            return None;
+        }
+        i = skip_whitespace(bytes, i + 1);
+        if name == key {
+            return Some((key_start, i));
+        }
+        i = skip_whitespace(bytes, skip_value(bytes, i));
+        if i < bytes.len() && bytes[i] == b',' {
+            i = skip_whitespace(bytes, i + 1);
+        }
+    }
+    None
 }
 
+/// Finds where element `n` of the array opening at `array` starts.
+fn nth_element(source: &str, array: usize, n: usize) -> Option<usize> {
+    let bytes = source.as_bytes();
+    if array >= bytes.len() || bytes[array] != b'[' {
+        return None;
+    }
+    let mut i = skip_whitespace(bytes, array + 1);
+    let mut index = 0;
+    while i < bytes.len() && bytes[i] != b']' {
+        if index == n {
+            return Some(i);
+        }
+        i = skip_whitespace(bytes, skip_value(bytes, i));
+        if i < bytes.len() && bytes[i] == b',' {
+            i = skip_whitespace(bytes, i + 1);
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Returns the index just past the value starting at `i`.
+fn skip_value(bytes: &[u8], mut i: usize) -> usize {
+    if i >= bytes.len() {
+        return i;
+    }
+    match bytes[i] {
+        b'"' => skip_string(bytes, i),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            while i < bytes.len() {
+                match bytes[i] {
+                    b'"' => { i = skip_string(bytes, i); continue; }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return i + 1;
+                        }
+                    }
+                    _ => (),
+                }
+                i += 1;
+            }
+            i
+        }
+        _ => {
+            while i < bytes.len() && !b",}] \t\r\n".contains(&bytes[i]) {
+                i += 1;
+            }
+            i
+        }
+    }
+}
+
+/// Returns the index just past the string whose opening quote is at `i`.
+fn skip_string(bytes: &[u8], mut i: usize) -> usize {
+    i += 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    i
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && b" \t\r\n".contains(&bytes[i]) {
+        i += 1;
+    }
+    i
+}
+
+/// Finds where `entity` is given as the value of an "id" key, skipping
+/// references to it from other entities.
+fn find_id(source: &str, entity: &str) -> Option<usize> {
+    let needle = format!("\"{}\"", entity);
+    let mut start = 0;
+    while let Some(found) = source[start..].find_str(&needle) {
+        let offset = start + found;
+        let before = source[..offset].trim_right();
+        if before.ends_with(":") && before[..before.len() - 1].trim_right().ends_with("\"id\"") {
+            return Some(offset);
+        }
+        start = offset + needle.len();
+    }
+    None
+}
+
+/// An entity from the source file.
+#[derive(Copy)]
+struct SourceEntity<'a> {
+    json: &'a Json,
+    id: Option<&'a str>,
+    //ID of the entity this one is nested in
+    nested_in: Option<&'a str>,
+}
+
+/// Appends `entities` and everything nested in their "children" to `output`
+/// in depth first order.
+fn flatten_entities<'a>(diag: &mut Diagnostics, entities: &'a [Json], nested_in: Option<&'a str>,
+output: &mut Vec<SourceEntity<'a>>) {
+    for json in entities.iter() {
+        let id = json.find("id").and_then(|x| x.as_string());
+        if id.is_none() {
+            diag.error(None, None, Some("id"),
+                format!("entity number {} in the file has no ID", output.len() + 1));
+        }
+        diag.check_keys(json, &["id", "components", "children"], id, None);
+        output.push(SourceEntity { json: json, id: id, nested_in: nested_in });
+
+        if let Some(children) = json.find("children") {
+            match children.as_array() {
+                Some(children) => flatten_entities(diag, children, id, output),
+                None => diag.error(id, None, Some("children"), "expected an array of entities".to_string()),
+            }
+        }
+    }
+}
+
+/// Runs the migrations from the file's "version" up to `latest`.
+fn migrate(root: &mut Json, latest: u64, migrations: &[fn(&mut Json)]) -> Result<(), CompileError> {
+    debug_assert_eq!(migrations.len() as u64, latest);
+
+    let version = root.find("version").and_then(|x| x.as_u64());
+    let message = match version {
+        Some(version) if version <= latest => {
+            for migrate in migrations[version as usize..].iter() {
+                (*migrate)(root);
+            }
+            return Ok(());
+        }
+        Some(version) => {
+            format!("version {} is newer than the compiler, which reads up to {}", version, latest)
+        }
+        None => "expected the format version as a number".to_string(),
+    };
+
+    let mut error = CompileError::new(message);
+    error.field = Some("version".to_string());
+    Err(error)
+}
 
 
 #[test]
@@ -137,12 +524,21 @@ fn scene_compile_test() {
                         \"scale\": 3
                     }
                 ]
+            },
+            {
+                \"id\": \"9a1f7c3e-5b2d-4e8a-9c6f-0d3b7e2a4f18\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"scale\": \"1 2 3\"
+                    }
+                ]
             }
         ]
     }".as_bytes();
     
     let mut output: Vec<u8> = Vec::new();
-    compile_scene(&mut input, &mut output);
+    compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).unwrap();
 
     let mut scene = Scene::new();
     scene.load(&mut &output[..]);
@@ -150,6 +546,478 @@ fn scene_compile_test() {
     assert!(scene.transform_system.exists(Entity::new(0, 0)));
     assert!(scene.transform_system.exists(Entity::new(1, 0)));
     assert!(scene.transform_system.exists(Entity::new(2, 0)));
+    assert!(scene.transform_system.exists(Entity::new(3, 0)));
     let tr_inst2 = scene.transform_system.get_instance(Entity::new(1, 0));
     assert_eq!(scene.transform_system.get_local_position(tr_inst2), Vector3::new(0.0, 4.0, 0.0));
+    assert_eq!(scene.transform_system.get_local_scale(tr_inst2), Vector3::new(2.0, 2.0, 2.0));
+    let tr_inst3 = scene.transform_system.get_instance(Entity::new(2, 0));
+    assert_eq!(scene.transform_system.get_local_scale(tr_inst3), Vector3::new(3.0, 3.0, 3.0));
+    let tr_inst4 = scene.transform_system.get_instance(Entity::new(3, 0));
+    assert_eq!(scene.transform_system.get_local_scale(tr_inst4), Vector3::new(1.0, 2.0, 3.0));
+}
+
+#[test]
+fn scene_hierarchy_test() {
+    let mut input = "{
+        \"version\": 0,
+        \"entities\": [
+            {
+                \"id\": \"da356da1-228f-40c8-ab48-3510a160c49f\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"position\": \"1 0 0\",
+                        \"rotation\": \"1 0 0 0\",
+                        \"scale\": 1
+                    }
+                ],
+                \"children\": [
+                    {
+                        \"id\": \"6b255092-90b5-42fe-a751-144b27d9870d\",
+                        \"components\": [
+                            {
+                                \"type\": \"transform\",
+                                \"position\": \"0 1 0\",
+                                \"rotation\": \"1 0 0 0\",
+                                \"scale\": 1
+                            }
+                        ]
+                    }
+                ]
+            },
+            {
+                \"id\": \"fc5f1c7d-d18b-422a-8cc8-316875176953\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"position\": \"0 0 1\",
+                        \"rotation\": \"1 0 0 0\",
+                        \"scale\": 1,
+                        \"parent\": \"6b255092-90b5-42fe-a751-144b27d9870d\"
+                    }
+                ]
+            }
+        ]
+    }".as_bytes();
+
+    let mut output: Vec<u8> = Vec::new();
+    compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).unwrap();
+
+    let mut scene = Scene::new();
+    scene.load(&mut &output[..]);
+
+    //Nested entities come right after their parent
+    let ref sys = scene.transform_system;
+    let root = sys.get_instance(Entity::new(0, 0));
+    let child = sys.get_instance(Entity::new(1, 0));
+    let grandchild = sys.get_instance(Entity::new(2, 0));
+    assert_eq!(sys.get_parent(child), root);
+    assert_eq!(sys.get_parent(grandchild), child);
+    assert_eq!(sys.get_world_position(grandchild), Vector3::new(1.0, 1.0, 1.0));
+}
+
+#[test]
+fn scene_errors_test() {
+    let mut input = "{
+        \"version\": 0,
+        \"entities\": [
+            {
+                \"id\": \"da356da1-228f-40c8-ab48-3510a160c49f\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"position\": \"0 0\",
+                        \"rotation\": \"1 0 0 0\",
+                        \"scale\": 1,
+                        \"parent\": \"6b255092-90b5-42fe-a751-144b27d9870d\"
+                    },
+                    {
+                        \"type\": \"light\"
+                    }
+                ]
+            }
+        ]
+    }".as_bytes();
+
+    //Every problem is reported and nothing is written
+    let mut output: Vec<u8> = Vec::new();
+    let errors = compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).err().unwrap();
+    assert!(output.is_empty());
+
+    let messages: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+    assert_eq!(messages, vec![
+        "9:25: entity da356da1-228f-40c8-ab48-3510a160c49f, component 0, \"position\": \
+            expected 3 numbers, found 2".to_string(),
+        "15:25: warning: entity da356da1-228f-40c8-ab48-3510a160c49f, component 1, \"type\": \
+            unknown component type \"light\", skipped".to_string(),
+        "12:25: entity da356da1-228f-40c8-ab48-3510a160c49f, component 0, \"parent\": \
+            parent 6b255092-90b5-42fe-a751-144b27d9870d doesn't exist".to_string(),
+    ]);
+
+    //Syntax errors come from the JSON parser
+    let mut input = "{ \"entities\": [ }".as_bytes();
+    let errors = compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].position.map(|x| x.0), Some(1));
+}
+
+#[test]
+fn scene_error_position_test() {
+    //Children before components, so the component comes after another "id"
+    let mut input = "{
+        \"version\": 0,
+        \"entities\": [
+            {
+                \"id\": \"da356da1-228f-40c8-ab48-3510a160c49f\",
+                \"children\": [
+                    {
+                        \"id\": \"6b255092-90b5-42fe-a751-144b27d9870d\",
+                        \"components\": [
+                            {
+                                \"type\": \"transform\",
+                                \"position\": \"0 0 0\"
+                            }
+                        ]
+                    }
+                ],
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"position\": \"0 0\"
+                    }
+                ]
+            }
+        ]
+    }".as_bytes();
+
+    let mut output: Vec<u8> = Vec::new();
+    let errors = compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].position, Some((20, 25)));
+}
+
+#[test]
+fn scene_cycle_test() {
+    let mut input = "{
+        \"version\": 0,
+        \"entities\": [
+            {
+                \"id\": \"da356da1-228f-40c8-ab48-3510a160c49f\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"parent\": \"6b255092-90b5-42fe-a751-144b27d9870d\"
+                    }
+                ]
+            },
+            {
+                \"id\": \"6b255092-90b5-42fe-a751-144b27d9870d\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"parent\": \"da356da1-228f-40c8-ab48-3510a160c49f\"
+                    }
+                ]
+            }
+        ]
+    }".as_bytes();
+
+    //The first link goes through, the one closing the loop is reported
+    let mut output: Vec<u8> = Vec::new();
+    let errors = compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).err().unwrap();
+    assert!(output.is_empty());
+
+    let messages: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+    assert_eq!(messages, vec![
+        "18:25: entity 6b255092-90b5-42fe-a751-144b27d9870d, component 0, \"parent\": \
+            parent da356da1-228f-40c8-ab48-3510a160c49f would create a cycle".to_string(),
+    ]);
+}
+
+#[test]
+fn scene_validation_test() {
+    let mut input = "{
+        \"version\": 0,
+        \"title\": \"merged\",
+        \"entities\": [
+            {
+                \"id\": \"da356da1-228f-40c8-ab48-3510a160c49f\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"postion\": \"0 0 0\",
+                        \"rotation\": \"1 0 0 0\",
+                        \"scale\": \"1 1 1\"
+                    },
+                    {
+                        \"type\": \"transform\",
+                        \"position\": \"0 0 0\",
+                        \"rotation\": \"1 0 0 0\",
+                        \"scale\": \"1 1 1\"
+                    }
+                ]
+            },
+            {
+                \"id\": \"da356da1-228f-40c8-ab48-3510a160c49f\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"position\": \"0 0 0\",
+                        \"rotation\": \"1 0 0 0\",
+                        \"scale\": \"1 1\"
+                    }
+                ]
+            }
+        ]
+    }".as_bytes();
+
+    let mut output: Vec<u8> = Vec::new();
+    let errors = compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).err().unwrap();
+    let found: Vec<(String, &str)> = errors.iter()
+        .map(|x| (x.field.clone().unwrap(), &x.message[..]))
+        .collect();
+
+    assert_eq!(found, vec![
+        ("title".to_string(), "unknown field"),
+        ("id".to_string(), "entity number 2 has the same ID as an earlier entity"),
+        ("postion".to_string(), "unknown field"),
+        ("type".to_string(), "entity already has a transform component"),
+        ("scale".to_string(), "expected 3 numbers, found 2"),
+    ]);
+
+    let mut input = "{ \"version\": 1, \"entities\": [] }".as_bytes();
+    let errors = compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].field, Some("version".to_string()));
+
+    let mut input = "{ \"entities\": [] }".as_bytes();
+    assert!(compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).is_err());
+}
+
+#[test]
+fn scene_migration_test() {
+    //Version 1 of a made up format renamed "entities" to "objects"
+    fn rename_entities(root: &mut Json) {
+        if let Json::Object(ref mut root) = *root {
+            if let Some(entities) = root.remove("entities") {
+                root.insert("objects".to_string(), entities);
+            }
+        }
+    }
+    let migrations = &[rename_entities as fn(&mut Json)];
+
+    let mut root = Json::from_str("{ \"version\": 0, \"entities\": [] }").unwrap();
+    migrate(&mut root, 1, migrations).unwrap();
+    assert!(root.find("entities").is_none());
+    assert!(root.find("objects").is_some());
+
+    //Files already at the latest version are left alone
+    let mut root = Json::from_str("{ \"version\": 1, \"entities\": [] }").unwrap();
+    migrate(&mut root, 1, migrations).unwrap();
+    assert!(root.find("entities").is_some());
+
+    let mut root = Json::from_str("{ \"version\": 2, \"entities\": [] }").unwrap();
+    let error = migrate(&mut root, 1, migrations).err().unwrap();
+    assert_eq!(error.field, Some("version".to_string()));
+}
+
+#[test]
+fn scene_value_syntax_test() {
+    use std::num::Float;
+
+    let mut input = "{
+        \"version\": 0,
+        \"entities\": [
+            {
+                \"id\": \"da356da1-228f-40c8-ab48-3510a160c49f\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"position\": \" 0  4 0 \",
+                        \"rotation\": [1, 0, 0, 0],
+                        \"scale\": { \"x\": 1, \"y\": 2, \"z\": 3 }
+                    }
+                ]
+            },
+            {
+                \"id\": \"6b255092-90b5-42fe-a751-144b27d9870d\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\"
+                    }
+                ]
+            },
+            {
+                \"id\": \"fc5f1c7d-d18b-422a-8cc8-316875176953\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"rotation\": { \"euler\": [0, 90, 0], \"order\": \"zxy\" }
+                    }
+                ]
+            }
+        ]
+    }".as_bytes();
+    let mut output: Vec<u8> = Vec::new();
+    compile_scene(&mut input, &mut output, &mut ComponentCompilers::new()).unwrap();
+
+    //The same scene written out in full
+    let mut input = "{
+        \"version\": 0,
+        \"entities\": [
+            {
+                \"id\": \"da356da1-228f-40c8-ab48-3510a160c49f\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"position\": \"0 4 0\",
+                        \"rotation\": { \"w\": 1, \"x\": 0, \"y\": 0, \"z\": 0 },
+                        \"scale\": \"1 2 3\"
+                    }
+                ]
+            },
+            {
+                \"id\": \"6b255092-90b5-42fe-a751-144b27d9870d\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"position\": \"0 0 0\",
+                        \"rotation\": \"1 0 0 0\",
+                        \"scale\": \"1 1 1\"
+                    }
+                ]
+            },
+            {
+                \"id\": \"fc5f1c7d-d18b-422a-8cc8-316875176953\",
+                \"components\": [
+                    {
+                        \"type\": \"transform\",
+                        \"position\": [0, 0, 0],
+                        \"rotation\": { \"axis\": \"0 1 0\", \"angle\": 90 },
+                        \"scale\": [1, 1, 1]
+                    }
+                ]
+            }
+        ]
+    }".as_bytes();
+    let mut expected: Vec<u8> = Vec::new();
+    compile_scene(&mut input, &mut expected, &mut ComponentCompilers::new()).unwrap();
+    assert_eq!(output, expected);
+
+    let mut scene = Scene::new();
+    scene.load(&mut &output[..]);
+    let inst = scene.transform_system.get_instance(Entity::new(2, 0));
+    let rotation = scene.transform_system.get_local_rotation(inst);
+    assert!((rotation.s - 0.5f32.sqrt()).abs() < 0.0001);
+    assert!((rotation.v.y - 0.5f32.sqrt()).abs() < 0.0001);
+}
+
+#[cfg(test)]
+struct NoteCompiler;
+
+#[cfg(test)]
+impl ::asset::compile::ComponentCompiler for NoteCompiler {
+    fn type_name(&self) -> &'static str {
+        "note"
+    }
+
+    fn compile(&mut self, _scene: &mut Scene, _uuids: &HashMap<Uuid, Entity>, source: &ComponentSource)
+    -> Vec<CompileError> {
+        let mut errors = source.check_keys(&["text"]);
+        let text = source.json.find("text").and_then(|x| x.as_string()).unwrap_or("");
+        errors.push(source.warning("text", text.to_string()));
+        errors
+    }
+}
+
+#[test]
+fn scene_component_compiler_test() {
+    let mut input = "{
+        \"version\": 0,
+        \"entities\": [
+            {
+                \"id\": \"da356da1-228f-40c8-ab48-3510a160c49f\",
+                \"components\": [
+                    { \"type\": \"transform\" },
+                    { \"type\": \"note\", \"text\": \"move this\" },
+                    { \"type\": \"light\" }
+                ]
+            }
+        ]
+    }".as_bytes();
+
+    let mut compilers = ComponentCompilers::new();
+    compilers.register(Box::new(NoteCompiler));
+
+    //Warnings don't stop the scene from compiling
+    let mut output: Vec<u8> = Vec::new();
+    let warnings = compile_scene(&mut input, &mut output, &mut compilers).unwrap();
+    let messages: Vec<&str> = warnings.iter().map(|x| &x.message[..]).collect();
+    assert_eq!(messages, vec!["move this", "unknown component type \"light\", skipped"]);
+    assert!(warnings.iter().all(|x| x.severity == Severity::Warning));
+    //Positions are found from what the compiler returned
+    assert_eq!(warnings[0].position, Some((8, 39)));
+
+    let mut scene = Scene::new();
+    scene.load(&mut &output[..]);
+    assert!(scene.transform_system.exists(Entity::new(0, 0)));
+}
+
+#[cfg(test)]
+struct Gravity(f32);
+
+#[cfg(test)]
+impl ::scene::SavedResource for Gravity {
+    fn name() -> &'static str { "gravity" }
+    fn save(&self, output: &mut Writer) { output.write_le_f32(self.0); }
+    fn load(input: &mut Reader) -> Gravity { Gravity(input.read_le_f32().ok().unwrap()) }
+}
+
+#[cfg(test)]
+struct GravityCompiler;
+
+#[cfg(test)]
+impl ::asset::compile::ResourceCompiler for GravityCompiler {
+    fn name(&self) -> &'static str {
+        "gravity"
+    }
+
+    fn compile(&mut self, scene: &mut Scene, json: &Json) -> Result<(), String> {
+        let gravity = try!(json.as_f64().ok_or("expected a number".to_string()));
+        scene.register_saved_resource::<Gravity>();
+        scene.insert_resource(Gravity(gravity as f32));
+        Ok(())
+    }
+}
+
+#[test]
+fn scene_resource_test() {
+    let mut input = "{
+        \"version\": 0,
+        \"resources\": {
+            \"gravity\": -9.8,
+            \"wind\": \"0 0 1\"
+        },
+        \"entities\": []
+    }".as_bytes();
+
+    let mut compilers = ComponentCompilers::new();
+    compilers.register_resource(Box::new(GravityCompiler));
+
+    //The resource is written into the compiled level
+    let mut output: Vec<u8> = Vec::new();
+    let warnings = compile_scene(&mut input, &mut output, &mut compilers).unwrap();
+    let messages: Vec<String> = warnings.iter().map(|x| x.to_string()).collect();
+    assert_eq!(messages, vec!["5:13: warning: \"wind\": unknown resource, skipped".to_string()]);
+
+    let mut scene = Scene::new();
+    scene.register_saved_resource::<Gravity>();
+    scene.load(&mut &output[..]);
+    assert_eq!(scene.resource::<Gravity>().map(|x| x.0), Some(-9.8));
+
+    let mut input = "{ \"version\": 0, \"resources\": { \"gravity\": \"down\" }, \"entities\": [] }"
+        .as_bytes();
+    let errors = compile_scene(&mut input, &mut output, &mut compilers).err().unwrap();
+    assert_eq!(errors[0].field, Some("gravity".to_string()));
+    assert_eq!(errors[0].message, "expected a number");
 }
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use scene::{Scene, Entity};
+use uuid::Uuid;
+use super::component::{ComponentCompiler, ComponentSource};
+use super::error::CompileError;
+use super::value::{parse_vector3, parse_rotation, parse_scale};
+
+/// A parent reference waiting for finish().
+struct Link {
+    child: Entity,
+    id: Option<String>,
+    index: usize,
+    parent: String,
+}
+
+/// Compiles "transform" components into the TransformSystem.
+///
+/// Parents are given by a "parent" UUID or by nesting the entity in the
+/// "children" of another. They are linked up in finish(), once every
+/// transform exists, in file order.
+pub struct TransformCompiler {
+    links: Vec<Link>,
+}
+
+impl TransformCompiler {
+    pub fn new() -> TransformCompiler {
+        TransformCompiler { links: Vec::new() }
+    }
+
+    fn link_error(&self, link: &Link, message: String) -> CompileError {
+        let mut error = CompileError::new(message);
+        error.entity = link.id.clone();
+        error.component = Some(link.index);
+        error.field = Some("parent".to_string());
+        error
+    }
+}
+
+impl ComponentCompiler for TransformCompiler {
+    fn type_name(&self) -> &'static str {
+        "transform"
+    }
+
+    fn prepare(&mut self, _scene: &mut Scene) {
+        self.links.clear();
+    }
+
+    fn compile(&mut self, scene: &mut Scene, _uuids: &HashMap<Uuid, Entity>, source: &ComponentSource)
+    -> Vec<CompileError> {
+        let ref mut sys = scene.transform_system;
+        let comp = source.json;
+        let mut errors = source.check_keys(&["position", "rotation", "scale", "parent"]);
+
+        //Create component if needed. Fields that are left out keep the defaults
+        //of a new instance.
+        let inst = sys.create_or_get_instance(source.entity);
+
+        if let Some(position) = comp.find("position") {
+            match parse_vector3(position) {
+                Ok(position) => sys.set_local_position(inst, position),
+                Err(message) => errors.push(source.error("position", message)),
+            }
+        }
+        if let Some(rotation) = comp.find("rotation") {
+            match parse_rotation(rotation) {
+                Ok(rotation) => sys.set_local_rotation(inst, rotation),
+                Err(message) => errors.push(source.error("rotation", message)),
+            }
+        }
+        if let Some(scale) = comp.find("scale") {
+            match parse_scale(scale) {
+                Ok(scale) => sys.set_local_scale(inst, scale),
+                Err(message) => errors.push(source.error("scale", message)),
+            }
+        }
+
+        let given = match comp.find("parent") {
+            Some(parent) => {
+                if parent.as_string().is_none() {
+                    errors.push(source.error("parent", "expected the ID of an entity".to_string()));
+                }
+                parent.as_string()
+            }
+            None => None,
+        };
+        let parent = match (given, source.nested_in) {
+            (Some(given), Some(nested)) if given != nested => {
+                errors.push(source.error("parent",
+                    format!("nested in {} but the parent is {}", nested, given)));
+                None
+            }
+            (Some(given), _) => Some(given),
+            (None, nested) => nested,
+        };
+
+        if let Some(parent) = parent {
+            self.links.push(Link {
+                child: source.entity,
+                id: source.id.map(|x| x.to_string()),
+                index: source.index,
+                parent: parent.to_string(),
+            });
+        }
+        errors
+    }
+
+    fn finish(&mut self, scene: &mut Scene, uuids: &HashMap<Uuid, Entity>) -> Vec<CompileError> {
+        let ref mut sys = scene.transform_system;
+        let mut errors = Vec::new();
+
+        for link in self.links.iter() {
+            let parent_en = match Uuid::parse_str(&link.parent).ok().and_then(|x| uuids.get(&x)) {
+                Some(parent_en) => *parent_en,
+                None => {
+                    errors.push(self.link_error(link, format!("parent {} doesn't exist", link.parent)));
+                    continue;
+                }
+            };
+            let parent = sys.get_instance(parent_en);
+            if !parent.is_valid() {
+                errors.push(self.link_error(link, format!("parent {} has no transform", link.parent)));
+                continue;
+            }
+
+            let child = sys.get_instance(link.child);
+            if sys.set_parent(child, parent).is_err() {
+                errors.push(self.link_error(link, format!("parent {} would create a cycle", link.parent)));
+            }
+        }
+        self.links.clear();
+        errors
+    }
+}
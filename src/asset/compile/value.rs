@@ -0,0 +1,189 @@
+use std::num::Float;
+use serialize::json::{self, Json};
+use cgmath::{Vector3, Quaternion};
+
+/// Reads `count` numbers from a string separated by whitespace, or from an
+/// array.
+fn parse_numbers(json: &Json, count: usize) -> Result<Vec<f32>, String> {
+    let mut numbers = Vec::with_capacity(count);
+    match *json {
+        Json::String(ref string) => {
+            for word in string.split(|c: char| c.is_whitespace()).filter(|x| !x.is_empty()) {
+                match word.parse::<f32>() {
+                    Ok(number) => numbers.push(number),
+                    Err(_) => return Err(format!("\"{}\" is not a number", word)),
+                }
+            }
+        }
+        Json::Array(ref array) => {
+            for value in array.iter() {
+                match value.as_f64() {
+                    Some(number) => numbers.push(number as f32),
+                    None => return Err(format!("{} is not a number", value)),
+                }
+            }
+        }
+        _ => return Err(format!("expected {} numbers", count)),
+    }
+
+    if numbers.len() != count {
+        return Err(format!("expected {} numbers, found {}", count, numbers.len()));
+    }
+    Ok(numbers)
+}
+
+/// Reads an object such as `{"x": 1, "y": 2, "z": 3}` with a number under
+/// each of `names`.
+fn parse_named(object: &json::Object, names: &[&str]) -> Result<Vec<f32>, String> {
+    try!(check_object(object, names));
+    names.iter().map(|name| {
+        match object.get(*name).and_then(|x| x.as_f64()) {
+            Some(number) => Ok(number as f32),
+            None => Err(format!("expected a number for \"{}\"", name)),
+        }
+    }).collect()
+}
+
+/// Fails on keys that aren't in `known`.
+fn check_object(object: &json::Object, known: &[&str]) -> Result<(), String> {
+    match object.keys().find(|x| !known.contains(&&x[..])) {
+        Some(key) => Err(format!("unknown key \"{}\"", key)),
+        None => Ok(()),
+    }
+}
+
+/// A vector is three numbers in a string or an array, or an object with
+/// "x", "y" and "z".
+pub fn parse_vector3(json: &Json) -> Result<Vector3<f32>, String> {
+    let comps = match json.as_object() {
+        Some(object) => try!(parse_named(object, &["x", "y", "z"])),
+        None => try!(parse_numbers(json, 3)),
+    };
+    Ok(Vector3::new(comps[0], comps[1], comps[2]))
+}
+
+/// Scale is either a single number for uniform scale or a vector.
+pub fn parse_scale(json: &Json) -> Result<Vector3<f32>, String> {
+    match json.as_f64() {
+        Some(scale) => {
+            let scale = scale as f32;
+            Ok(Vector3::new(scale, scale, scale))
+        }
+        None => parse_vector3(json),
+    }
+}
+
+/// A rotation is one of:
+///
+/// * A quaternion in s x y z order, in a string or an array.
+/// * An object with "w", "x", "y" and "z".
+/// * `{"euler": "90 0 45", "order": "zxy"}` in degrees. The rotations about
+///   the parent's axes are applied in `order`, "xyz" if it is left out.
+/// * `{"axis": "0 1 0", "angle": 90}` in degrees.
+pub fn parse_rotation(json: &Json) -> Result<Quaternion<f32>, String> {
+    let object = match json.as_object() {
+        Some(object) => object,
+        None => {
+            let comps = try!(parse_numbers(json, 4));
+            return Ok(Quaternion::new(comps[0], comps[1], comps[2], comps[3]));
+        }
+    };
+
+    if let Some(angles) = object.get("euler") {
+        try!(check_object(object, &["euler", "order"]));
+        let angles = try!(parse_vector3(angles));
+        let order = match object.get("order") {
+            Some(order) => try!(order.as_string().ok_or("expected the order as a string".to_string())),
+            None => "xyz",
+        };
+        euler_rotation(angles, order)
+    }
+    else if let Some(axis) = object.get("axis") {
+        try!(check_object(object, &["axis", "angle"]));
+        let axis = try!(parse_vector3(axis));
+        let angle = match object.get("angle").and_then(|x| x.as_f64()) {
+            Some(angle) => angle,
+            None => return Err("expected the angle in degrees".to_string()),
+        };
+        axis_angle(axis, angle)
+    }
+    else {
+        let comps = try!(parse_named(object, &["w", "x", "y", "z"]));
+        Ok(Quaternion::new(comps[0], comps[1], comps[2], comps[3]))
+    }
+}
+
+fn euler_rotation(angles: Vector3<f32>, order: &str) -> Result<Quaternion<f32>, String> {
+    let mut axes: Vec<char> = order.chars().collect();
+    axes.sort();
+    if axes != vec!['x', 'y', 'z'] {
+        return Err(format!("\"{}\" is not an order of x, y and z", order));
+    }
+
+    let mut rotation = Quaternion::identity();
+    for axis in order.chars() {
+        let step = match axis {
+            'x' => try!(axis_angle(Vector3::new(1.0, 0.0, 0.0), angles.x as f64)),
+            'y' => try!(axis_angle(Vector3::new(0.0, 1.0, 0.0), angles.y as f64)),
+            _ => try!(axis_angle(Vector3::new(0.0, 0.0, 1.0), angles.z as f64)),
+        };
+        rotation = step.mul_q(&rotation);
+    }
+    Ok(rotation)
+}
+
+fn axis_angle(axis: Vector3<f32>, degrees: f64) -> Result<Quaternion<f32>, String> {
+    let (x, y, z) = (axis.x as f64, axis.y as f64, axis.z as f64);
+    let length = (x * x + y * y + z * z).sqrt();
+    if length == 0.0 {
+        return Err("the axis can't be zero".to_string());
+    }
+
+    let half = degrees.to_radians() / 2.0;
+    let sin = half.sin() / length;
+    Ok(Quaternion::new(half.cos() as f32, (x * sin) as f32, (y * sin) as f32, (z * sin) as f32))
+}
+
+
+
+#[test]
+fn value_test() {
+    let parse = |source: &str| Json::from_str(source).unwrap();
+
+    assert_eq!(parse_vector3(&parse("\"1\\t 2  3 \"")), Ok(Vector3::new(1.0, 2.0, 3.0)));
+    assert_eq!(parse_vector3(&parse("[1, 2, 3]")), Ok(Vector3::new(1.0, 2.0, 3.0)));
+    assert!(parse_vector3(&parse("{ \"x\": 1, \"y\": 2, \"w\": 3 }")).is_err());
+    assert!(parse_vector3(&parse("[1, 2]")).is_err());
+    assert_eq!(parse_scale(&parse("2")), Ok(Vector3::new(2.0, 2.0, 2.0)));
+    assert_eq!(parse_scale(&parse("\"1 2 3\"")), Ok(Vector3::new(1.0, 2.0, 3.0)));
+
+    assert_eq!(parse_rotation(&parse("{ \"euler\": \"0 0 0\" }")), Ok(Quaternion::identity()));
+    assert!(parse_rotation(&parse("{ \"euler\": \"0 0 0\", \"order\": \"xxz\" }")).is_err());
+    assert!(parse_rotation(&parse("{ \"axis\": \"0 0 0\", \"angle\": 90 }")).is_err());
+}
+
+#[test]
+fn euler_test() {
+    use cgmath::ApproxEq;
+
+    let parse = |source: &str| parse_rotation(&Json::from_str(source).unwrap()).unwrap();
+    let about = |degrees: f32, x: f32, y: f32, z: f32| {
+        let half = degrees.to_radians() / 2.0;
+        Quaternion::new(half.cos(), x * half.sin(), y * half.sin(), z * half.sin())
+    };
+
+    //y first, then z, then x, each about the parent's axes
+    let rotation = parse("{ \"euler\": \"30 45 60\", \"order\": \"yzx\" }");
+    let expected = about(30.0, 1.0, 0.0, 0.0)
+        .mul_q(&about(60.0, 0.0, 0.0, 1.0))
+        .mul_q(&about(45.0, 0.0, 1.0, 0.0));
+    assert!(rotation.approx_eq(&expected));
+
+    //90 about y, then 90 about x, is a third of a turn about the diagonal
+    let rotation = parse("{ \"euler\": [90, 90, 0], \"order\": \"zyx\" }");
+    assert!(rotation.approx_eq(&Quaternion::new(0.5, 0.5, 0.5, 0.5)));
+    assert!(rotation.approx_eq(&parse("{ \"axis\": \"1 1 1\", \"angle\": 120 }")));
+
+    let rotation = parse("{ \"euler\": [90, 90, 90], \"order\": \"zyx\" }");
+    assert!(rotation.approx_eq(&parse("{ \"axis\": \"1 0 1\", \"angle\": 180 }")));
+}
@@ -0,0 +1,6 @@
+extern crate cgmath;
+extern crate serialize;
+extern crate uuid;
+
+pub mod asset;
+pub mod scene;
@@ -1,20 +1,17 @@
 #![feature(plugin)]
 #![plugin(glium_macros)]
 
-extern crate cgmath;
+extern crate cantus;
 extern crate getopts;
-extern crate serialize;
-extern crate uuid;
 extern crate glutin;
 #[macro_use]
 extern crate glium;
 
 use std::old_path::Path;
 use getopts::Options;
+use cantus::asset;
 
 mod application;
-mod asset;
-mod scene;
 
 fn main() {
     let args = std::os::args();
@@ -35,7 +32,10 @@ fn main() {
 
         let path = Path::new(matches.opt_str("compile").unwrap());
         let output_folder = Path::new(matches.opt_str("output").unwrap());
-        asset::compile::compile_path(&path, &output_folder);
+        //Games with their own components build the registry themselves and
+        //call compile_path() from their own binary
+        let mut compilers = asset::compile::ComponentCompilers::new();
+        asset::compile::compile_path(&path, &output_folder, &mut compilers);
     }
     else if matches.opt_present("pack") {
 
@@ -0,0 +1,100 @@
+use scene::entity::Entity;
+
+/// A point in time for change detection.
+///
+/// Systems count ticks with a ChangeTracker and store the tick of the last
+/// change next to each component. Tick 0 is never current, so it can be
+/// used to ask for everything.
+#[derive(Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Tick(pub u32);
+
+/// The current tick of a system.
+///
+/// # Description
+///
+/// A system keeps one of these and a `Tick` column per kind of change. Every
+/// edit writes the current tick into the column with mark(), and the system
+/// calls advance() once per update(). Readers remember the tick they last
+/// looked at and iterate over everything that changed since.
+pub struct ChangeTracker {
+    tick: Tick,
+}
+
+impl ChangeTracker {
+    pub fn new() -> ChangeTracker {
+        ChangeTracker { tick: Tick(1) }
+    }
+
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+
+    pub fn advance(&mut self) {
+        self.tick = Tick(self.tick.0 + 1);
+    }
+
+    /// Records a change to row `idx` of a tick column.
+    #[inline]
+    pub fn mark(&self, ticks: &mut [Tick], idx: usize) {
+        ticks[idx] = self.tick;
+    }
+
+    /// Iterates over the entities whose tick in `ticks` is `since` or later.
+    ///
+    /// Changes made during the `since` tick itself are included, so reading
+    /// twice in the same tick reports some entities twice rather than
+    /// missing any. Removed components don't show up at all, watch the
+    /// EntityManager for those.
+    pub fn changed_since<'a>(&self, entities: &'a [Entity], ticks: &'a [Tick], since: Tick)
+    -> ChangedIterator<'a> {
+        ChangedIterator {
+            entities: entities,
+            ticks: ticks,
+            since: since,
+            index: 0,
+        }
+    }
+}
+
+pub struct ChangedIterator<'a> {
+    entities: &'a [Entity],
+    ticks: &'a [Tick],
+    since: Tick,
+    index: usize,
+}
+
+impl<'a> Iterator for ChangedIterator<'a> {
+    type Item = Entity;
+    fn next(&mut self) -> Option<Entity> {
+        while self.index < self.ticks.len() {
+            let index = self.index;
+            self.index += 1;
+            if self.ticks[index] >= self.since {
+                return Some(self.entities[index]);
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn change_tracker_test() {
+    use scene::EntityManager;
+
+    let mut em = EntityManager::new();
+    let entities: Vec<Entity> = (0..4).map(|_| em.create()).collect();
+    let mut ticks: Vec<Tick> = (0..4).map(|_| Tick(0)).collect();
+    let mut tracker = ChangeTracker::new();
+
+    tracker.mark(&mut ticks, 1);
+    let start = tracker.tick();
+    tracker.advance();
+    tracker.mark(&mut ticks, 3);
+
+    let all: Vec<Entity> = tracker.changed_since(&entities, &ticks, Tick(0)).collect();
+    assert_eq!(all, entities);
+    let changed: Vec<Entity> = tracker.changed_since(&entities, &ticks, start).collect();
+    assert_eq!(changed, vec![entities[1], entities[3]]);
+    let changed: Vec<Entity> = tracker.changed_since(&entities, &ticks, tracker.tick()).collect();
+    assert_eq!(changed, vec![entities[3]]);
+}
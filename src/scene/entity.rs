@@ -1,24 +1,48 @@
+use std::old_io::IoResult;
+
+//Entities are 32 bits by default: 24 bits of index and 8 bits of generation.
+//The wide_entities feature makes them 64 bits: 32 bits of each.
+#[cfg(not(feature = "wide_entities"))]
+pub type EntityId = u32;
+#[cfg(feature = "wide_entities")]
+pub type EntityId = u64;
+
+#[cfg(not(feature = "wide_entities"))]
+pub type Generation = u8;
+#[cfg(feature = "wide_entities")]
+pub type Generation = u32;
+
 #[derive(Debug, Eq, PartialEq, Copy, Hash)]
 pub struct Entity {
-    pub id: u32
+    pub id: EntityId
 }
 
+#[cfg(not(feature = "wide_entities"))]
 pub const ENTITY_INDEX_BITS: u32 = 24;
-pub const ENTITY_INDEX_MASK: u32 = (1 << ENTITY_INDEX_BITS) - 1;
+#[cfg(feature = "wide_entities")]
+pub const ENTITY_INDEX_BITS: u32 = 32;
+pub const ENTITY_INDEX_MASK: EntityId = (1 << ENTITY_INDEX_BITS) - 1;
 
-//If this is increased beyond 8 bits, you need to update the EntityManager as well
+#[cfg(not(feature = "wide_entities"))]
 pub const ENTITY_GENERATION_BITS: u32 = 8;
-pub const ENTITY_GENERATION_MASK: u32 = (1 << ENTITY_GENERATION_BITS) - 1;
+#[cfg(feature = "wide_entities")]
+pub const ENTITY_GENERATION_BITS: u32 = 32;
+pub const ENTITY_GENERATION_MASK: EntityId = (1 << ENTITY_GENERATION_BITS) - 1;
+
+/// The last generation before a slot wraps around.
+pub const MAX_GENERATION: Generation = ENTITY_GENERATION_MASK as Generation;
+
+//An id with every bit set can be used by systems as a fake entity
+const ENTITY_ID_NONE: EntityId = !0;
 
 impl Entity {
-    pub fn new(index: u32, generation: u8) -> Entity {
-        assert!(index <= ENTITY_INDEX_MASK);
-        assert!(generation <= ENTITY_GENERATION_MASK as u8);
+    pub fn new(index: u32, generation: Generation) -> Entity {
+        assert!(index as EntityId <= ENTITY_INDEX_MASK);
+        assert!(generation <= MAX_GENERATION);
 
-        let id = ((generation as u32) << ENTITY_INDEX_BITS) | index;
+        let id = ((generation as EntityId) << ENTITY_INDEX_BITS) | index as EntityId;
 
-        //MAX can be used by systems as a fake entity
-        assert!(id != ::std::u32::MAX);
+        assert!(id != ENTITY_ID_NONE);
 
         Entity {
             id: id
@@ -26,10 +50,44 @@ impl Entity {
     }
 
     pub fn index(self) -> u32 {
-        return self.id & ENTITY_INDEX_MASK;
+        return (self.id & ENTITY_INDEX_MASK) as u32;
+    }
+
+    pub fn generation(self) -> Generation {
+        return ((self.id >> ENTITY_INDEX_BITS) & ENTITY_GENERATION_MASK) as Generation;
+    }
+
+    /// Writes the id using the width of EntityId.
+    #[cfg(not(feature = "wide_entities"))]
+    pub fn write(self, output: &mut Writer) -> IoResult<()> {
+        output.write_le_u32(self.id)
+    }
+
+    #[cfg(feature = "wide_entities")]
+    pub fn write(self, output: &mut Writer) -> IoResult<()> {
+        output.write_le_u64(self.id)
+    }
+
+    /// Reads an id written by write().
+    #[cfg(not(feature = "wide_entities"))]
+    pub fn read(input: &mut Reader) -> IoResult<Entity> {
+        input.read_le_u32().map(|id| Entity { id: id })
     }
 
-    pub fn generation(self) -> u8 {
-        return ((self.id >> ENTITY_INDEX_BITS) & ENTITY_GENERATION_MASK) as u8;
+    #[cfg(feature = "wide_entities")]
+    pub fn read(input: &mut Reader) -> IoResult<Entity> {
+        input.read_le_u64().map(|id| Entity { id: id })
     }
 }
+
+#[test]
+fn index_generation_test() {
+    let en = Entity::new(ENTITY_INDEX_MASK as u32, MAX_GENERATION - 1);
+    assert_eq!(en.index(), ENTITY_INDEX_MASK as u32);
+    assert_eq!(en.generation(), MAX_GENERATION - 1);
+
+    let mut output: Vec<u8> = Vec::new();
+    en.write(&mut output).unwrap();
+    assert_eq!(output.len(), ::std::mem::size_of::<EntityId>());
+    assert_eq!(Entity::read(&mut &output[..]).unwrap(), en);
+}
@@ -0,0 +1,246 @@
+use std::mem;
+use scene::Scene;
+use scene::entity::Entity;
+use scene::entity_instance::Instance;
+use scene::transform_system::HierarchyError;
+
+/// An entity as seen by EntityCommands.
+///
+/// # Description
+///
+/// Entities created through EntityCommands don't exist until the commands are
+/// played back, so create() hands out a placeholder instead. Placeholders can
+/// be used in later commands from the same buffer and are swapped for the real
+/// entity during playback.
+#[derive(Copy, Debug, Eq, PartialEq)]
+pub enum EntityRef {
+    Existing(Entity),
+    /// Index into the entities returned by EntityCommands::apply().
+    Pending(u32),
+}
+
+/// A command that couldn't be played back. It is skipped and the rest of the
+/// buffer carries on.
+#[derive(Copy, Debug, Eq, PartialEq)]
+pub enum CommandError {
+    /// set_parent() or detach() named an entity without a transform.
+    NoTransform(Entity),
+    /// The transform system turned down the new parent of the entity.
+    Hierarchy(Entity, HierarchyError),
+}
+
+enum Command {
+    Create,
+    Destroy(EntityRef),
+    AddComponent(EntityRef, Box<FnMut(&mut Scene, Entity) + 'static>),
+    RemoveComponent(EntityRef, Box<FnMut(&mut Scene, Entity) + 'static>),
+    SetParent(EntityRef, Option<EntityRef>),
+}
+
+/// Records entity and component changes so they can be made later.
+///
+/// # Description
+///
+/// Creating or destroying things while looping over a system moves instances
+/// around under the loop. Record the changes here instead and let
+/// `Scene::sync()` play them back once nobody is looking.
+///
+/// Commands are played back in the order they were recorded.
+pub struct EntityCommands {
+    commands: Vec<Command>,
+    pending_count: u32,
+}
+
+impl EntityCommands {
+    pub fn new() -> EntityCommands {
+        EntityCommands {
+            commands: Vec::new(),
+            pending_count: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Queues the creation of an entity. Returns a placeholder for it.
+    pub fn create(&mut self) -> EntityRef {
+        let placeholder = EntityRef::Pending(self.pending_count);
+        self.pending_count += 1;
+        self.commands.push(Command::Create);
+        placeholder
+    }
+
+    /// Queues the destruction of an entity. The systems hear about it when
+    /// the scene syncs.
+    pub fn destroy(&mut self, entity: EntityRef) {
+        self.commands.push(Command::Destroy(entity));
+    }
+
+    /// Queues a function that adds a component to the entity.
+    pub fn add_component<F>(&mut self, entity: EntityRef, add: F)
+    where F: FnMut(&mut Scene, Entity) + 'static {
+        self.commands.push(Command::AddComponent(entity, Box::new(add)));
+    }
+
+    /// Queues a function that removes a component from the entity.
+    pub fn remove_component<F>(&mut self, entity: EntityRef, remove: F)
+    where F: FnMut(&mut Scene, Entity) + 'static {
+        self.commands.push(Command::RemoveComponent(entity, Box::new(remove)));
+    }
+
+    pub fn add_transform(&mut self, entity: EntityRef) {
+        self.add_component(entity, |scene: &mut Scene, en: Entity| {
+            scene.transform_system.create_or_get_instance(en);
+        });
+    }
+
+    pub fn remove_transform(&mut self, entity: EntityRef) {
+        self.remove_component(entity, |scene: &mut Scene, en: Entity| {
+            if scene.transform_system.exists(en) {
+                scene.transform_system.destroy(en, &mut scene.entity_manager);
+            }
+        });
+    }
+
+    /// Queues a TransformSystem::set_parent(). Both entities need a transform
+    /// by the time the command is played back.
+    pub fn set_parent(&mut self, child: EntityRef, parent: EntityRef) {
+        self.commands.push(Command::SetParent(child, Some(parent)));
+    }
+
+    /// Queues making the child's transform a root.
+    pub fn detach(&mut self, child: EntityRef) {
+        self.commands.push(Command::SetParent(child, None));
+    }
+
+    /// Plays back and clears the recorded commands.
+    ///
+    /// Returns the entities that were created, indexed by placeholder, and the
+    /// commands that failed.
+    pub fn apply(&mut self, scene: &mut Scene) -> (Vec<Entity>, Vec<CommandError>) {
+        let commands = mem::replace(&mut self.commands, Vec::new());
+        self.pending_count = 0;
+
+        let mut created = Vec::new();
+        let mut errors = Vec::new();
+        for command in commands.into_iter() {
+            match command {
+                Command::Create => {
+                    created.push(scene.entity_manager.create());
+                }
+                Command::Destroy(entity) => {
+                    let en = resolve(entity, &created);
+                    if scene.entity_manager.alive(en) {
+                        scene.entity_manager.destroy(en);
+                    }
+                }
+                Command::AddComponent(entity, mut add) => {
+                    (*add)(scene, resolve(entity, &created));
+                }
+                Command::RemoveComponent(entity, mut remove) => {
+                    (*remove)(scene, resolve(entity, &created));
+                }
+                Command::SetParent(child, parent) => {
+                    let ref mut sys = scene.transform_system;
+                    let child_en = resolve(child, &created);
+                    let child = sys.get_instance(child_en);
+                    if !child.is_valid() {
+                        errors.push(CommandError::NoTransform(child_en));
+                        continue;
+                    }
+                    let parent = match parent {
+                        Some(parent) => {
+                            let parent_en = resolve(parent, &created);
+                            let parent = sys.get_instance(parent_en);
+                            if !parent.is_valid() {
+                                errors.push(CommandError::NoTransform(parent_en));
+                                continue;
+                            }
+                            parent
+                        }
+                        None => Instance::none(),
+                    };
+                    if let Err(e) = sys.set_parent(child, parent) {
+                        errors.push(CommandError::Hierarchy(child_en, e));
+                    }
+                }
+            }
+        }
+
+        (created, errors)
+    }
+}
+
+fn resolve(entity: EntityRef, created: &[Entity]) -> Entity {
+    match entity {
+        EntityRef::Existing(en) => en,
+        EntityRef::Pending(idx) => created[idx as usize],
+    }
+}
+
+#[test]
+fn entity_commands_test() {
+    let mut scene = Scene::new();
+
+    let parent = scene.entity_manager.create();
+    let doomed = scene.entity_manager.create();
+    scene.transform_system.create(parent);
+    scene.transform_system.create(doomed);
+
+    //Record while iterating over the system
+    for _ in 0..scene.transform_system.count() {
+        let child = scene.commands.create();
+        scene.commands.add_transform(child);
+        scene.commands.set_parent(child, EntityRef::Existing(parent));
+    }
+    scene.commands.destroy(EntityRef::Existing(doomed));
+    assert_eq!(scene.transform_system.count(), 2);
+
+    let (created, errors) = scene.sync();
+    assert_eq!(created.len(), 2);
+    assert!(errors.is_empty());
+    assert!(scene.commands.is_empty());
+    assert!(!scene.entity_manager.alive(doomed));
+    assert!(!scene.transform_system.exists(doomed));
+
+    let parent_inst = scene.transform_system.get_instance(parent);
+    assert_eq!(scene.transform_system.get_child_entities(parent_inst), created);
+
+    //Components can be removed again, and placeholders start over
+    let extra = scene.commands.create();
+    assert_eq!(extra, EntityRef::Pending(0));
+    scene.commands.remove_transform(EntityRef::Existing(created[0]));
+    scene.sync();
+    assert!(!scene.transform_system.exists(created[0]));
+    assert!(scene.entity_manager.alive(created[0]));
+}
+
+#[test]
+fn entity_commands_error_test() {
+    let mut scene = Scene::new();
+
+    let a = scene.entity_manager.create();
+    let b = scene.entity_manager.create();
+    let bare = scene.entity_manager.create();
+    scene.transform_system.create(a);
+    scene.transform_system.create(b);
+
+    //Failed commands are skipped, the ones after them still run
+    scene.commands.set_parent(EntityRef::Existing(bare), EntityRef::Existing(a));
+    scene.commands.set_parent(EntityRef::Existing(b), EntityRef::Existing(bare));
+    scene.commands.set_parent(EntityRef::Existing(b), EntityRef::Existing(a));
+    scene.commands.set_parent(EntityRef::Existing(a), EntityRef::Existing(b));
+    let (_, errors) = scene.sync();
+    assert_eq!(errors, vec![
+        CommandError::NoTransform(bare),
+        CommandError::NoTransform(bare),
+        CommandError::Hierarchy(a, HierarchyError::Cycle),
+    ]);
+    assert!(scene.commands.is_empty());
+
+    let ia = scene.transform_system.get_instance(a);
+    let ib = scene.transform_system.get_instance(b);
+    assert_eq!(scene.transform_system.get_parent(ib), ia);
+    assert_eq!(scene.transform_system.get_parent(ia), Instance::none());
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+use std::marker::PhantomData;
+
 /// EntityInstance is an index into a system (that you might want to use).
 ///
 /// # Description
@@ -20,7 +23,8 @@
 /// EntityInstance, then don't.
 ///
 /// Systems that do use EntityInstance should wrap it to prevent users from
-/// using EntityInstances that were retrieved from another system.
+/// using EntityInstances that were retrieved from another system. Instance<S>
+/// below does exactly that.
 
 #[derive(Copy, Debug, Eq, PartialEq)]
 pub struct EntityInstance {
@@ -45,3 +49,66 @@ impl EntityInstance {
         self.index as usize
     }
 }
+
+/// An EntityInstance that can only be used with the system `S`.
+///
+/// # Description
+///
+/// Systems hand these out instead of a bare EntityInstance so that an
+/// instance from one system doesn't compile when passed to another.
+///
+/// The frame is the system's frame counter at the time the instance was
+/// handed out. Systems bump the counter whenever instances may have moved
+/// and check it in debug builds, which catches instances that were kept
+/// around for too long.
+pub struct Instance<S> {
+    instance: EntityInstance,
+    frame: u32,
+    system: PhantomData<S>,
+}
+
+impl<S> Instance<S> {
+    pub fn new(instance: EntityInstance, frame: u32) -> Instance<S> {
+        Instance { instance: instance, frame: frame, system: PhantomData }
+    }
+
+    pub fn none() -> Instance<S> {
+        Instance::new(EntityInstance::none(), 0)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.instance.is_valid()
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// The plain index, for use inside the system.
+    pub fn untyped(&self) -> EntityInstance {
+        self.instance
+    }
+
+    #[inline]
+    pub fn idx(&self) -> usize {
+        self.instance.idx()
+    }
+}
+
+//Derive would require S itself to implement these
+impl<S> Copy for Instance<S> { }
+
+impl<S> PartialEq for Instance<S> {
+    //The frame doesn't matter, it's only there for debug checks
+    fn eq(&self, other: &Instance<S>) -> bool {
+        self.instance == other.instance
+    }
+}
+
+impl<S> Eq for Instance<S> { }
+
+impl<S> fmt::Debug for Instance<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Instance({})", self.instance.index)
+    }
+}
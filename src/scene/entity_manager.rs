@@ -1,10 +1,23 @@
 use std::collections::{RingBuf};
-use scene::entity::{Entity, ENTITY_INDEX_MASK};
+use scene::entity::{Entity, EntityId, Generation, ENTITY_INDEX_MASK, MAX_GENERATION};
+
+/// What destroy() does with a slot whose generation is used up.
+#[derive(Copy, Debug, Eq, PartialEq)]
+pub enum GenerationPolicy {
+    /// The generation wraps back to 0 and the slot is reused. A handle that
+    /// is old enough will look alive again.
+    Wrap,
+    /// The slot is never handed out again, so stale handles stay dead.
+    Retire,
+}
 
 pub struct EntityManager {
-    generation: Vec<u8>,
+    generation: Vec<Generation>,
+    //Slots that ran out of generations under GenerationPolicy::Retire
+    retired: Vec<bool>,
     free_indices: RingBuf<u32>,
-    destroyed: Vec<Entity>
+    destroyed: Vec<Entity>,
+    generation_policy: GenerationPolicy,
 }
 
 static MINIMUM_FREE_INDICES: usize = 1024;
@@ -13,13 +26,28 @@ impl EntityManager {
     pub fn new() -> EntityManager {
         EntityManager {
             generation: Vec::new(),
+            retired: Vec::new(),
             free_indices: RingBuf::new(),
-            destroyed: Vec::new()
+            destroyed: Vec::new(),
+            generation_policy: GenerationPolicy::Wrap,
         }
     }
 
+    pub fn get_generation_policy(&self) -> GenerationPolicy {
+        self.generation_policy
+    }
+
+    pub fn set_generation_policy(&mut self, policy: GenerationPolicy) {
+        self.generation_policy = policy;
+    }
+
+    /// Returns false for entities that were destroyed or never created by
+    /// this manager.
     pub fn alive(&self, entity: Entity) -> bool {
-        self.generation[entity.index() as usize] == entity.generation()
+        let index = entity.index() as usize;
+        index < self.generation.len() &&
+            !self.retired[index] &&
+            self.generation[index] == entity.generation()
     }
 
     pub fn create(&mut self) -> Entity {
@@ -30,23 +58,50 @@ impl EntityManager {
         }
         else {
             self.generation.push(0);
+            self.retired.push(false);
             index = (self.generation.len() - 1) as u32;
 
-            assert!(index <= ENTITY_INDEX_MASK);
+            assert!(index as EntityId <= ENTITY_INDEX_MASK);
         }
 
         return Entity::new(index, self.generation[index as usize]);
     }
 
+    /// Destroys the entity. Does nothing if it isn't alive, so a stale handle
+    /// can't destroy whatever entity reuses its slot.
     pub fn destroy(&mut self, entity: Entity) {
+        if !self.alive(entity) {
+            return;
+        }
         let index = entity.index();
-        self.generation[index as usize] += 1;
-        self.free_indices.push_back(index);
+        let generation = self.generation[index as usize];
+
+        if generation == MAX_GENERATION {
+            self.generation[index as usize] = 0;
+            match self.generation_policy {
+                GenerationPolicy::Wrap => self.free_indices.push_back(index),
+                GenerationPolicy::Retire => self.retired[index as usize] = true,
+            }
+        }
+        else {
+            self.generation[index as usize] = generation + 1;
+            self.free_indices.push_back(index);
+        }
 
         //Keep track of the destroyed entities
         self.destroyed.push(entity);
     }
 
+    /// Returns one past the highest index handed out so far.
+    pub fn index_count(&self) -> usize {
+        self.generation.len()
+    }
+
+    /// Returns the number of slots that can't be used anymore.
+    pub fn retired_count(&self) -> usize {
+        self.retired.iter().filter(|x| **x).count()
+    }
+
     pub fn poll_destroyed(&self) -> &[Entity] {
         self.destroyed.as_slice()
     }
@@ -64,6 +119,83 @@ fn sequential_test() {
     let mut manager = EntityManager::new();
     for i in 0..2*MINIMUM_FREE_INDICES {
         let en = manager.create();
-        assert_eq!(en.id, i as u32);
+        assert_eq!(en.id, i as EntityId);
+    }
+}
+
+#[test]
+fn unknown_index_test() {
+    let mut manager = EntityManager::new();
+    assert!(!manager.alive(Entity::new(5, 0)));
+
+    let en = manager.create();
+    assert!(manager.alive(en));
+    assert!(!manager.alive(Entity::new(en.index() + 1, 0)));
+}
+
+#[test]
+fn double_destroy_test() {
+    let mut manager = EntityManager::new();
+    let en = manager.create();
+    let other = manager.create();
+
+    //The second destroy is ignored, so the slot is only freed once
+    manager.destroy(en);
+    manager.destroy(en);
+    assert_eq!(manager.generation[en.index() as usize], 1);
+    assert_eq!(manager.free_indices.len(), 1);
+    assert_eq!(manager.poll_destroyed().to_vec(), vec![en]);
+
+    //Handles that were never alive don't touch live slots
+    manager.destroy(Entity::new(other.index(), other.generation() + 1));
+    assert!(manager.alive(other));
+    assert_eq!(manager.free_indices.len(), 1);
+}
+
+#[test]
+fn generation_wrap_test() {
+    let mut manager = EntityManager::new();
+    let first = manager.create();
+
+    //Churn through every slot's generations several times over
+    for _ in 0..3000000 {
+        let en = manager.create();
+        manager.destroy(en);
+        manager.clear_destroyed();
+    }
+
+    //Wrapping reuses slots, so only the minimum free list was ever needed
+    assert!(manager.generation.len() <= MINIMUM_FREE_INDICES + 2);
+    assert!(manager.alive(first));
+    assert_eq!(manager.retired_count(), 0);
+}
+
+//Wide entities have too many generations to run out in a test
+#[cfg(not(feature = "wide_entities"))]
+#[test]
+fn generation_retire_test() {
+    let mut manager = EntityManager::new();
+    manager.set_generation_policy(GenerationPolicy::Retire);
+
+    let mut stale = Vec::new();
+    for i in 0..3000000 {
+        let en = manager.create();
+        if i % 1000 == 0 {
+            stale.push(en);
+        }
+        manager.destroy(en);
+        manager.clear_destroyed();
+    }
+
+    //Each slot was used once per generation before being retired
+    assert!(manager.retired_count() > 0);
+    assert!(manager.retired_count() * (MAX_GENERATION as usize + 1) <= 3000000);
+    assert!(stale.iter().all(|x| !manager.alive(*x)));
+
+    //Fresh entities never land on a retired slot
+    for _ in 0..10000 {
+        let en = manager.create();
+        assert!(manager.alive(en));
+        assert!(!manager.retired[en.index() as usize]);
     }
 }
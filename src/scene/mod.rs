@@ -1,29 +1,164 @@
+use std::any::Any;
 use std::collections::HashMap;
+use std::mem;
 
-pub use scene::entity::Entity;
-pub use scene::entity_manager::EntityManager;
-pub use scene::transform_system::TransformSystem;
+pub use scene::change::{ChangeTracker, ChangedIterator, Tick};
+pub use scene::entity::{Entity, EntityId};
+pub use scene::entity_commands::{EntityCommands, EntityRef, CommandError};
+pub use scene::entity_instance::{EntityInstance, Instance};
+pub use scene::entity_manager::{EntityManager, GenerationPolicy};
+pub use scene::query::{Join, Query2, Query3, query2, query3};
+pub use scene::resource::{Resources, SavedResource};
+pub use scene::soa::{SoaValue, permute};
+pub use scene::system::System;
+pub use scene::transform_system::{TransformSystem, TransformInstance, HierarchyError, UpdateMode,
+    Layout, Space, DestroyPolicy, ValidationError};
 
+mod change;
 mod entity;
+mod entity_commands;
 mod entity_instance;
 mod entity_manager;
+mod query;
+mod resource;
+#[macro_use]
+mod soa;
+mod system;
 mod transform_system;
 
 
+/// Version of the compiled scene format, written first by Scene::save() and
+/// checked by Scene::load(). Bump it whenever a system changes what it saves.
+///
+/// Version 1 saves transform scale as three numbers instead of one. Files
+/// from before the version was written start with the entity count instead,
+/// so they need recompiling too.
+///
+/// The version is followed by the width of entity handles in bits, since
+/// systems save entities at the width of the build (see the wide_entities
+/// feature).
+pub const FORMAT_VERSION: u32 = 1;
+
+fn entity_bits() -> u32 {
+    (mem::size_of::<EntityId>() * 8) as u32
+}
+
+/// The entities of a level and all of their components.
+///
+/// The transform system is built in since every other system depends on the
+/// hierarchy. Other systems are added with register_system(). Scene-wide
+/// values that don't belong to an entity go in the resources.
 pub struct Scene {
     pub entity_manager: EntityManager,
     pub transform_system: TransformSystem,
+    systems: Vec<Box<System>>,
+    resources: Resources,
+
+    /// Changes to make at the next sync().
+    pub commands: EntityCommands,
 }
 
 impl Scene {
     pub fn new() -> Scene {
         Scene {
             entity_manager: EntityManager::new(),
-            transform_system: TransformSystem::new()
+            transform_system: TransformSystem::new(),
+            systems: Vec::new(),
+            resources: Resources::new(),
+            commands: EntityCommands::new(),
+        }
+    }
+
+    /// Adds a system to the scene. Panics if a system with the same name is
+    /// already registered.
+    pub fn register_system(&mut self, system: Box<System>) {
+        let name = system.name();
+        assert!(name != self.transform_system.name() && self.systems.iter().all(|x| x.name() != name),
+            "A system named {} is already registered.", name);
+        self.systems.push(system);
+    }
+
+    pub fn system<T: System>(&self) -> Option<&T> {
+        self.systems.iter()
+            .filter_map(|x| x.as_any().downcast_ref::<T>())
+            .next()
+    }
+
+    pub fn system_mut<T: System>(&mut self) -> Option<&mut T> {
+        self.systems.iter_mut()
+            .filter_map(|x| x.as_any_mut().downcast_mut::<T>())
+            .next()
+    }
+
+    /// Adds a scene-wide resource, replacing any resource of the same type.
+    pub fn insert_resource<T: Any>(&mut self, resource: T) {
+        self.resources.insert(resource);
+    }
+
+    pub fn resource<T: Any>(&self) -> Option<&T> {
+        self.resources.get::<T>()
+    }
+
+    pub fn resource_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.resources.get_mut::<T>()
+    }
+
+    /// Saves resources of type `T` with the scene. Call this before load()
+    /// to get them back.
+    pub fn register_saved_resource<T: SavedResource>(&mut self) {
+        self.resources.register_saved::<T>();
+    }
+
+    /// Tells the systems about destroyed entities, then updates each one.
+    /// Call this once per frame after sync().
+    pub fn update(&mut self) {
+        self.flush_destroyed();
+
+        self.transform_system.update();
+        for system in self.systems.iter_mut() {
+            system.update();
+        }
+    }
+
+    /// Plays back the queued entity commands and tells the systems about
+    /// destroyed entities. Call this between updates, when nothing is
+    /// iterating over the systems.
+    ///
+    /// Returns the entities created for the command placeholders, and the
+    /// commands that failed and were skipped.
+    pub fn sync(&mut self) -> (Vec<Entity>, Vec<CommandError>) {
+        let mut commands = mem::replace(&mut self.commands, EntityCommands::new());
+        let result = commands.apply(self);
+        self.flush_destroyed();
+        result
+    }
+
+    fn flush_destroyed(&mut self) {
+        //Destroying can cascade to more entities, so keep going until nothing
+        //new shows up
+        loop {
+            let destroyed: Vec<Entity> = self.entity_manager.poll_destroyed()
+                .iter().map(|x| *x).collect();
+            if destroyed.is_empty() {
+                break;
+            }
+            self.entity_manager.clear_destroyed();
+
+            self.transform_system.handle_destroyed(&destroyed, &mut self.entity_manager);
+            for system in self.systems.iter_mut() {
+                system.handle_destroyed(&destroyed, &mut self.entity_manager);
+            }
         }
     }
 
     pub fn load(&mut self, input: &mut Reader) {
+        let version = input.read_le_u32().ok().unwrap();
+        assert!(version == FORMAT_VERSION,
+            "Scene was saved with format version {}, expected {}. Recompile it.", version, FORMAT_VERSION);
+        let bits = input.read_le_u32().ok().unwrap();
+        assert!(bits == entity_bits(),
+            "Scene was saved with {} bit entities, this build uses {}. Recompile it.", bits, entity_bits());
+
         //Create all the entities we need
         let entity_count = input.read_le_u32().ok().unwrap();
         let mut entities = Vec::with_capacity(entity_count as usize);
@@ -31,12 +166,193 @@ impl Scene {
             entities.push(self.entity_manager.create());
         }
 
-        //Load each system
-        self.transform_system.load(input, &entities);
+        //Load each system. Every system's data is stored under its name, so
+        //data for systems this scene doesn't have can be skipped.
+        let system_count = input.read_le_u32().ok().unwrap();
+        for _ in 0..system_count {
+            let (name, data) = read_chunk(input);
+
+            if name == self.transform_system.name() {
+                self.transform_system.load(&mut &data[..], &entities);
+            }
+            else if let Some(system) = self.systems.iter_mut().find(|x| x.name() == name) {
+                system.load(&mut &data[..], &entities);
+            }
+        }
+
+        self.resources.load(input);
     }
 
+    /// Writes the format version, the entity width, the entity count and every
+    /// system and saved resource. Entities are saved by index, so load()
+    /// creates one for every index handed out so far, dead ones included.
     pub fn save(&self, output: &mut Writer) {
+        output.write_le_u32(FORMAT_VERSION);
+        output.write_le_u32(entity_bits());
+        output.write_le_u32(self.entity_manager.index_count() as u32);
+
         //Save each system
-        self.transform_system.save(output);
+        output.write_le_u32(1 + self.systems.len() as u32);
+
+        let mut data = Vec::new();
+        self.transform_system.save(&mut data);
+        write_chunk(output, self.transform_system.name(), &data);
+
+        for system in self.systems.iter() {
+            let mut data = Vec::new();
+            system.save(&mut data);
+            write_chunk(output, system.name(), &data);
+        }
+
+        self.resources.save(output);
     }
 }
+
+/// Writes a named block of data. The length goes first so readers can skip
+/// blocks they don't know about.
+fn write_chunk(output: &mut Writer, name: &str, data: &[u8]) {
+    output.write_le_u32(name.len() as u32);
+    output.write_str(name);
+    output.write_le_u32(data.len() as u32);
+    output.write_all(data);
+}
+
+fn read_chunk(input: &mut Reader) -> (String, Vec<u8>) {
+    let name_length = input.read_le_u32().ok().unwrap() as usize;
+    let name = String::from_utf8(input.read_exact(name_length).ok().unwrap()).ok().unwrap();
+    let data_length = input.read_le_u32().ok().unwrap() as usize;
+    let data = input.read_exact(data_length).ok().unwrap();
+    (name, data)
+}
+
+#[cfg(test)]
+struct TagSystem {
+    entities: Vec<Entity>,
+    updates: u32,
+}
+
+#[cfg(test)]
+impl System for TagSystem {
+    fn name(&self) -> &'static str { "tag" }
+
+    fn load(&mut self, input: &mut Reader, entities: &[Entity]) {
+        let count = input.read_le_u32().ok().unwrap();
+        for _ in 0..count {
+            let en = Entity::read(input).ok().unwrap();
+            self.entities.push(entities[en.index() as usize]);
+        }
+    }
+
+    fn save(&self, output: &mut Writer) {
+        output.write_le_u32(self.entities.len() as u32);
+        for en in &self.entities { en.write(output); }
+    }
+
+    fn handle_destroyed(&mut self, entities: &[Entity], _: &mut EntityManager) {
+        self.entities.retain(|x| !entities.contains(x));
+    }
+
+    fn exists(&self, entity: Entity) -> bool { self.entities.contains(&entity) }
+    fn count(&self) -> usize { self.entities.len() }
+    fn update(&mut self) { self.updates += 1; }
+
+    fn as_any(&self) -> &Any { self }
+    fn as_any_mut(&mut self) -> &mut Any { self }
+}
+
+#[cfg(test)]
+struct Gravity(f32);
+
+#[cfg(test)]
+impl SavedResource for Gravity {
+    fn name() -> &'static str { "gravity" }
+    fn save(&self, output: &mut Writer) { output.write_le_f32(self.0); }
+    fn load(input: &mut Reader) -> Gravity { Gravity(input.read_le_f32().ok().unwrap()) }
+}
+
+#[test]
+fn resource_test() {
+    struct GameTime(f32);
+
+    let mut scene = Scene::new();
+    scene.register_saved_resource::<Gravity>();
+    assert!(scene.resource::<Gravity>().is_none());
+
+    scene.insert_resource(Gravity(-9.8));
+    scene.insert_resource(GameTime(0.0));
+    scene.resource_mut::<GameTime>().unwrap().0 += 0.5;
+    assert_eq!(scene.resource::<GameTime>().unwrap().0, 0.5);
+    assert_eq!(scene.resource::<Gravity>().unwrap().0, -9.8);
+
+    //Only registered resources travel with the scene
+    let mut output: Vec<u8> = Vec::new();
+    scene.save(&mut output);
+
+    let mut loaded = Scene::new();
+    loaded.register_saved_resource::<Gravity>();
+    loaded.load(&mut &output[..]);
+    assert_eq!(loaded.resource::<Gravity>().unwrap().0, -9.8);
+    assert!(loaded.resource::<GameTime>().is_none());
+
+    let mut plain = Scene::new();
+    plain.load(&mut &output[..]);
+    assert!(plain.resource::<Gravity>().is_none());
+}
+
+#[test]
+fn system_registry_test() {
+    let mut scene = Scene::new();
+    scene.register_system(Box::new(TagSystem { entities: Vec::new(), updates: 0 }));
+
+    let e1 = scene.entity_manager.create();
+    let e2 = scene.entity_manager.create();
+    scene.transform_system.create(e1);
+    scene.transform_system.create(e2);
+    scene.system_mut::<TagSystem>().unwrap().entities.push(e1);
+    scene.system_mut::<TagSystem>().unwrap().entities.push(e2);
+
+    //Round trip through a file. Data for unknown systems is skipped.
+    let mut output: Vec<u8> = Vec::new();
+    scene.save(&mut output);
+
+    let mut loaded = Scene::new();
+    loaded.register_system(Box::new(TagSystem { entities: Vec::new(), updates: 0 }));
+    loaded.load(&mut &output[..]);
+    assert_eq!(loaded.system::<TagSystem>().unwrap().count(), 2);
+    assert_eq!(loaded.transform_system.count(), 2);
+
+    let mut plain = Scene::new();
+    plain.load(&mut &output[..]);
+    assert_eq!(plain.transform_system.count(), 2);
+
+    //Destroyed entities reach every system
+    scene.entity_manager.destroy(e1);
+    scene.update();
+    assert!(!scene.transform_system.exists(e1));
+    assert!(!scene.system::<TagSystem>().unwrap().exists(e1));
+    assert!(scene.system::<TagSystem>().unwrap().exists(e2));
+    assert_eq!(scene.system::<TagSystem>().unwrap().updates, 1);
+}
+
+#[test]
+#[should_panic(expected = "Recompile it")]
+fn format_version_test() {
+    //Before the version was written, files started with the entity count
+    let mut output: Vec<u8> = Vec::new();
+    output.write_le_u32(3);
+    output.write_le_u32(0);
+
+    Scene::new().load(&mut &output[..]);
+}
+
+#[test]
+#[should_panic(expected = "bit entities")]
+fn entity_width_test() {
+    //Written by a build with the other handle width
+    let mut output: Vec<u8> = Vec::new();
+    output.write_le_u32(FORMAT_VERSION);
+    output.write_le_u32(if entity_bits() == 32 { 64 } else { 32 });
+    output.write_le_u32(0);
+
+    Scene::new().load(&mut &output[..]);
+}
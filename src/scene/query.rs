@@ -0,0 +1,149 @@
+use scene::entity::Entity;
+
+/// A system that can take part in a query.
+///
+/// # Description
+///
+/// Queries walk the entities of one system and look each of them up in the
+/// others, so a system only needs to list its entities and find the instance
+/// of an entity.
+pub trait Join {
+    type Instance: Copy;
+
+    /// Every entity with a component in this system.
+    fn entities(&self) -> &[Entity];
+
+    /// Returns the instance of an entity, or None if it doesn't have a
+    /// component in this system.
+    fn lookup(&self, entity: Entity) -> Option<Self::Instance>;
+}
+
+/// Iterates over the entities that have a component in both systems.
+///
+/// The system with fewer components drives the iteration. The systems stay
+/// borrowed while iterating, so collect the results first if you need to
+/// change them.
+pub fn query2<'a, A: Join, B: Join>(a: &'a A, b: &'a B) -> Query2<'a, A, B> {
+    Query2 {
+        a: a,
+        b: b,
+        driver: smallest(&[a.entities(), b.entities()]),
+        index: 0,
+    }
+}
+
+/// Iterates over the entities that have a component in all three systems.
+/// See query2().
+pub fn query3<'a, A: Join, B: Join, C: Join>(a: &'a A, b: &'a B, c: &'a C) -> Query3<'a, A, B, C> {
+    Query3 {
+        a: a,
+        b: b,
+        c: c,
+        driver: smallest(&[a.entities(), b.entities(), c.entities()]),
+        index: 0,
+    }
+}
+
+fn smallest<'a>(sets: &[&'a [Entity]]) -> &'a [Entity] {
+    let mut smallest = sets[0];
+    for set in sets.iter() {
+        if set.len() < smallest.len() {
+            smallest = *set;
+        }
+    }
+    smallest
+}
+
+pub struct Query2<'a, A: 'a, B: 'a> {
+    a: &'a A,
+    b: &'a B,
+    driver: &'a [Entity],
+    index: usize,
+}
+
+impl<'a, A: Join, B: Join> Iterator for Query2<'a, A, B> {
+    type Item = (Entity, A::Instance, B::Instance);
+    fn next(&mut self) -> Option<(Entity, A::Instance, B::Instance)> {
+        while self.index < self.driver.len() {
+            let en = self.driver[self.index];
+            self.index += 1;
+
+            if let (Some(a), Some(b)) = (self.a.lookup(en), self.b.lookup(en)) {
+                return Some((en, a, b));
+            }
+        }
+        None
+    }
+}
+
+pub struct Query3<'a, A: 'a, B: 'a, C: 'a> {
+    a: &'a A,
+    b: &'a B,
+    c: &'a C,
+    driver: &'a [Entity],
+    index: usize,
+}
+
+impl<'a, A: Join, B: Join, C: Join> Iterator for Query3<'a, A, B, C> {
+    type Item = (Entity, A::Instance, B::Instance, C::Instance);
+    fn next(&mut self) -> Option<(Entity, A::Instance, B::Instance, C::Instance)> {
+        while self.index < self.driver.len() {
+            let en = self.driver[self.index];
+            self.index += 1;
+
+            if let (Some(a), Some(b), Some(c)) = (self.a.lookup(en), self.b.lookup(en), self.c.lookup(en)) {
+                return Some((en, a, b, c));
+            }
+        }
+        None
+    }
+}
+
+
+
+#[cfg(test)]
+struct TagSet {
+    entities: Vec<Entity>,
+}
+
+#[cfg(test)]
+impl Join for TagSet {
+    type Instance = usize;
+
+    fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    fn lookup(&self, entity: Entity) -> Option<usize> {
+        self.entities.iter().position(|x| *x == entity)
+    }
+}
+
+#[test]
+fn query_test() {
+    use scene::{EntityManager, TransformSystem};
+
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+    let e: Vec<Entity> = (0..6).map(|_| em.create()).collect();
+    for en in &e[..5] {
+        tr.create(*en);
+    }
+    let meshes = TagSet { entities: vec![e[5], e[3], e[1], e[0]] };
+    let visible = TagSet { entities: vec![e[0], e[3]] };
+
+    //Driven by the meshes, so results come in their order. e5 has no
+    //transform.
+    let found: Vec<(Entity, usize)> = query2(&tr, &meshes)
+        .map(|(en, inst, mesh)| {
+            assert_eq!(tr.get_instance(en), inst);
+            (en, mesh)
+        }).collect();
+    assert_eq!(found, vec![(e[3], 1), (e[1], 2), (e[0], 3)]);
+
+    let found: Vec<Entity> = query3(&meshes, &tr, &visible).map(|x| x.0).collect();
+    assert_eq!(found, vec![e[0], e[3]]);
+
+    let empty = TagSet { entities: Vec::new() };
+    assert_eq!(query3(&tr, &meshes, &empty).count(), 0);
+}
@@ -0,0 +1,114 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use super::{read_chunk, write_chunk};
+
+/// A resource that can be saved with the scene.
+///
+/// The name is used to find the resource in a scene file, so it must be
+/// unique and shouldn't change once scenes have been saved with it.
+pub trait SavedResource: Any {
+    fn name() -> &'static str;
+    fn save(&self, output: &mut Writer);
+    fn load(input: &mut Reader) -> Self;
+}
+
+/// How to save and load one registered resource type.
+struct ResourceFns {
+    name: &'static str,
+    type_id: TypeId,
+    save: fn(&Any, &mut Writer),
+    load: fn(&mut Reader) -> Box<Any>,
+}
+
+fn save_resource<T: SavedResource>(resource: &Any, output: &mut Writer) {
+    resource.downcast_ref::<T>().unwrap().save(output);
+}
+
+fn load_resource<T: SavedResource>(input: &mut Reader) -> Box<Any> {
+    let resource: T = SavedResource::load(input);
+    Box::new(resource) as Box<Any>
+}
+
+/// Scene-wide values keyed by their type, such as game time or gravity.
+///
+/// # Description
+///
+/// There is at most one resource of each type. Resources aren't saved unless
+/// their type is registered with register_saved().
+pub struct Resources {
+    values: HashMap<TypeId, Box<Any>>,
+    saved: Vec<ResourceFns>,
+}
+
+impl Resources {
+    pub fn new() -> Resources {
+        Resources {
+            values: HashMap::new(),
+            saved: Vec::new(),
+        }
+    }
+
+    /// Adds a resource, replacing any resource of the same type.
+    pub fn insert<T: Any>(&mut self, resource: T) {
+        self.values.insert(TypeId::of::<T>(), Box::new(resource) as Box<Any>);
+    }
+
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>()).and_then(|x| x.downcast_ref::<T>())
+    }
+
+    pub fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>()).and_then(|x| x.downcast_mut::<T>())
+    }
+
+    pub fn contains<T: Any>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Saves resources of type `T` with the scene from now on. Registering a
+    /// type twice does nothing, but panics if another type already uses the
+    /// same name.
+    pub fn register_saved<T: SavedResource>(&mut self) {
+        let name = <T as SavedResource>::name();
+        if self.saved.iter().any(|x| x.type_id == TypeId::of::<T>()) {
+            return;
+        }
+        assert!(self.saved.iter().all(|x| x.name != name),
+            "A resource named {} is already registered.", name);
+
+        self.saved.push(ResourceFns {
+            name: name,
+            type_id: TypeId::of::<T>(),
+            save: save_resource::<T>,
+            load: load_resource::<T>,
+        });
+    }
+
+    /// Writes every registered resource that is present, each under its name.
+    pub fn save(&self, output: &mut Writer) {
+        let present: Vec<&ResourceFns> = self.saved.iter()
+            .filter(|x| self.values.contains_key(&x.type_id))
+            .collect();
+
+        output.write_le_u32(present.len() as u32);
+        for fns in present.iter() {
+            let mut data = Vec::new();
+            (fns.save)(&*self.values[fns.type_id], &mut data);
+            write_chunk(output, fns.name, &data);
+        }
+    }
+
+    /// Reads resources written by save(). Resources that aren't registered
+    /// are skipped.
+    pub fn load(&mut self, input: &mut Reader) {
+        let count = input.read_le_u32().ok().unwrap();
+        for _ in 0..count {
+            let (name, data) = read_chunk(input);
+
+            if let Some(fns) = self.saved.iter().find(|x| x.name == name) {
+                let resource = (fns.load)(&mut &data[..]);
+                self.values.insert(fns.type_id, resource);
+            }
+        }
+    }
+}
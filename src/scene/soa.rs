@@ -0,0 +1,304 @@
+use cgmath::{Vector3, Quaternion};
+use scene::entity_instance::EntityInstance;
+
+/// A value that can be stored in a saved column of a `soa_storage!` struct.
+pub trait SoaValue {
+    fn write_value(&self, output: &mut Writer);
+    fn read_value(input: &mut Reader) -> Self;
+}
+
+impl SoaValue for f32 {
+    fn write_value(&self, output: &mut Writer) {
+        output.write_le_f32(*self);
+    }
+
+    fn read_value(input: &mut Reader) -> f32 {
+        input.read_le_f32().ok().unwrap()
+    }
+}
+
+impl SoaValue for u32 {
+    fn write_value(&self, output: &mut Writer) {
+        output.write_le_u32(*self);
+    }
+
+    fn read_value(input: &mut Reader) -> u32 {
+        input.read_le_u32().ok().unwrap()
+    }
+}
+
+impl SoaValue for bool {
+    fn write_value(&self, output: &mut Writer) {
+        output.write_u8(*self as u8);
+    }
+
+    fn read_value(input: &mut Reader) -> bool {
+        input.read_u8().ok().unwrap() != 0
+    }
+}
+
+impl SoaValue for Vector3<f32> {
+    fn write_value(&self, output: &mut Writer) {
+        output.write_le_f32(self.x);
+        output.write_le_f32(self.y);
+        output.write_le_f32(self.z);
+    }
+
+    fn read_value(input: &mut Reader) -> Vector3<f32> {
+        Vector3::new(
+            input.read_le_f32().ok().unwrap(),
+            input.read_le_f32().ok().unwrap(),
+            input.read_le_f32().ok().unwrap())
+    }
+}
+
+impl SoaValue for Quaternion<f32> {
+    fn write_value(&self, output: &mut Writer) {
+        output.write_le_f32(self.s);
+        output.write_le_f32(self.v.x);
+        output.write_le_f32(self.v.y);
+        output.write_le_f32(self.v.z);
+    }
+
+    fn read_value(input: &mut Reader) -> Quaternion<f32> {
+        Quaternion::new(
+            input.read_le_f32().ok().unwrap(),
+            input.read_le_f32().ok().unwrap(),
+            input.read_le_f32().ok().unwrap(),
+            input.read_le_f32().ok().unwrap())
+    }
+}
+
+impl SoaValue for EntityInstance {
+    fn write_value(&self, output: &mut Writer) {
+        output.write_le_u32(self.index);
+    }
+
+    fn read_value(input: &mut Reader) -> EntityInstance {
+        EntityInstance::new(input.read_le_u32().ok().unwrap())
+    }
+}
+
+/// Returns `values` reordered so that position `i` holds `values[order[i]]`.
+pub fn permute<T: Copy>(values: &[T], order: &[usize]) -> Vec<T> {
+    order.iter().map(|x| values[*x]).collect()
+}
+
+/// Declares a structure of arrays that stores one component per entity.
+///
+/// # Description
+///
+/// Every field becomes a `Vec` column. Alongside the columns, the struct
+/// keeps the entity of each row and a map from entity to EntityInstance, and
+/// gets the plumbing every array based system needs: push, swap_remove,
+/// copy, reserve, permute, and binary read/write.
+///
+/// Fields under `saved` are written by write() in the order they are
+/// declared, so their types must implement SoaValue. Fields under
+/// `transient` aren't saved. They hold their default after read() and the
+/// system is expected to rebuild them.
+///
+/// The storage knows nothing about what the values mean. If a column holds
+/// instances, the system has to fix them up after rows move.
+///
+/// ```ignore
+/// soa_storage! {
+///     struct LightData {
+///         saved {
+///             colors: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0),
+///             ranges: f32 = 10.0,
+///         }
+///         transient {
+///             visible: bool = false,
+///         }
+///     }
+/// }
+/// ```
+///
+/// Outside this crate, bring it in with `#[macro_use] extern crate cantus;`.
+#[macro_export]
+macro_rules! soa_storage {
+    (
+        $(#[$attr:meta])*
+        struct $name:ident {
+            saved { $($field:ident: $ty:ty = $default:expr),* $(,)* }
+            transient { $($tfield:ident: $tty:ty = $tdefault:expr),* $(,)* }
+        }
+    ) => {
+        $(#[$attr])*
+        pub struct $name {
+            pub map: ::std::collections::HashMap<$crate::scene::Entity, $crate::scene::EntityInstance>,
+            pub entities: Vec<$crate::scene::Entity>,
+            $(pub $field: Vec<$ty>,)*
+            $(pub $tfield: Vec<$tty>,)*
+        }
+
+        #[allow(dead_code)]
+        impl $name {
+            pub fn new() -> $name {
+                $name {
+                    map: ::std::collections::HashMap::new(),
+                    entities: Vec::new(),
+                    $($field: Vec::new(),)*
+                    $($tfield: Vec::new(),)*
+                }
+            }
+
+            pub fn len(&self) -> usize {
+                self.entities.len()
+            }
+
+            /// The length of every column, for consistency checks.
+            pub fn column_lengths(&self) -> Vec<usize> {
+                vec![$(self.$field.len(),)* $(self.$tfield.len(),)*]
+            }
+
+            pub fn reserve(&mut self, additional: usize) {
+                self.entities.reserve(additional);
+                $(self.$field.reserve(additional);)*
+                $(self.$tfield.reserve(additional);)*
+            }
+
+            /// Adds a row of default values for the entity.
+            pub fn push(&mut self, entity: $crate::scene::Entity) -> $crate::scene::EntityInstance {
+                let instance = $crate::scene::EntityInstance::new(self.entities.len() as u32);
+                self.entities.push(entity);
+                $(self.$field.push($default);)*
+                $(self.$tfield.push($tdefault);)*
+                self.map.insert(entity, instance);
+                instance
+            }
+
+            /// Copies the row at `src` over the row at `dst`. The map is
+            /// pointed at `dst`, so `src` is left as a stale duplicate.
+            pub fn copy(&mut self,
+                        src: $crate::scene::EntityInstance,
+                        dst: $crate::scene::EntityInstance) {
+                let (src_index, dst_index) = (src.idx(), dst.idx());
+                self.entities[dst_index] = self.entities[src_index];
+                $(self.$field[dst_index] = self.$field[src_index];)*
+                $(self.$tfield[dst_index] = self.$tfield[src_index];)*
+                self.map.insert(self.entities[dst_index], dst);
+            }
+
+            /// Removes a row by moving the last row into its place.
+            ///
+            /// Returns the old instance of the moved row, or none if the
+            /// removed row was the last.
+            pub fn swap_remove(&mut self, instance: $crate::scene::EntityInstance)
+                    -> $crate::scene::EntityInstance {
+                let last = $crate::scene::EntityInstance::new(self.entities.len() as u32 - 1);
+                self.map.remove(&self.entities[instance.idx()]);
+
+                let moved = if instance != last {
+                    self.copy(last, instance);
+                    last
+                }
+                else {
+                    $crate::scene::EntityInstance::none()
+                };
+
+                self.entities.pop();
+                $(self.$field.pop();)*
+                $(self.$tfield.pop();)*
+                moved
+            }
+
+            /// Reorders every column so that row `i` holds the old row
+            /// `order[i]`.
+            pub fn permute(&mut self, order: &[usize]) {
+                self.entities = $crate::scene::permute(&self.entities, order);
+                $(self.$field = $crate::scene::permute(&self.$field, order);)*
+                $(self.$tfield = $crate::scene::permute(&self.$tfield, order);)*
+
+                for (idx, entity) in self.entities.iter().enumerate() {
+                    self.map.insert(*entity, $crate::scene::EntityInstance::new(idx as u32));
+                }
+            }
+
+            /// Writes the row count, the entities and the saved columns.
+            pub fn write(&self, output: &mut Writer) {
+                output.write_le_u32(self.entities.len() as u32);
+                for en in &self.entities { en.write(output); }
+                $(for value in &self.$field { $crate::scene::SoaValue::write_value(value, output); })*
+            }
+
+            /// Appends rows written by write(). `id_map` maps the entity ids
+            /// in the file to the entities created for this scene.
+            ///
+            /// Values are read as written. Columns holding instances count
+            /// from the first row of the file, so the system has to offset
+            /// them by the old length when appending.
+            pub fn read(&mut self, input: &mut Reader, id_map: &[$crate::scene::Entity]) {
+                let length = input.read_le_u32().ok().unwrap() as usize;
+                self.reserve(length);
+
+                for _ in 0..length {
+                    let idx = $crate::scene::Entity::read(input).ok().unwrap().index();
+                    let en = id_map[idx as usize];
+                    let instance = $crate::scene::EntityInstance::new(self.entities.len() as u32);
+                    self.entities.push(en);
+                    self.map.insert(en, instance);
+                }
+                $(for _ in 0..length { self.$field.push($crate::scene::SoaValue::read_value(input)); })*
+                $(for _ in 0..length { self.$tfield.push($tdefault); })*
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+soa_storage! {
+    struct TestData {
+        saved {
+            values: u32 = 0,
+            speeds: f32 = 1.0,
+        }
+        transient {
+            flags: bool = true,
+        }
+    }
+}
+
+#[test]
+fn soa_storage_test() {
+    use scene::{Entity, EntityManager};
+
+    let mut em = EntityManager::new();
+    let mut data = TestData::new();
+    let entities: Vec<Entity> = (0..4).map(|_| em.create()).collect();
+    for (i, en) in entities.iter().enumerate() {
+        let inst = data.push(*en);
+        data.values[inst.idx()] = i as u32;
+    }
+    assert_eq!(data.speeds, vec![1.0, 1.0, 1.0, 1.0]);
+
+    //Removing moves the last row into the hole
+    let inst = data.map[entities[1]];
+    let moved = data.swap_remove(inst);
+    assert_eq!(moved, EntityInstance::new(3));
+    assert_eq!(data.values, vec![0, 3, 2]);
+    assert_eq!(data.map[entities[3]], EntityInstance::new(1));
+    assert!(!data.map.contains_key(&entities[1]));
+    let inst = data.map[entities[2]];
+    assert_eq!(data.swap_remove(inst), EntityInstance::none());
+
+    data.push(entities[1]);
+    data.permute(&[2, 0, 1]);
+    assert_eq!(data.entities, vec![entities[1], entities[0], entities[3]]);
+    assert_eq!(data.map[entities[0]], EntityInstance::new(1));
+
+    //Transient columns get their default back
+    data.flags[0] = false;
+    let mut output: Vec<u8> = Vec::new();
+    data.write(&mut output);
+
+    let mut loaded = TestData::new();
+    loaded.read(&mut &output[..], &entities);
+    assert_eq!(loaded.entities, data.entities);
+    assert_eq!(loaded.values, data.values);
+    assert_eq!(loaded.flags, vec![true, true, true]);
+    assert_eq!(loaded.column_lengths(), vec![3, 3, 3]);
+}
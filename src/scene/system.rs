@@ -0,0 +1,35 @@
+use std::any::Any;
+use scene::entity::Entity;
+use scene::entity_manager::EntityManager;
+
+/// A component system that the Scene can drive without knowing its type.
+///
+/// # Description
+///
+/// Register systems with `Scene::register_system()`. From then on the scene
+/// saves and loads them with the rest of the scene, tells them about destroyed
+/// entities and updates them once per frame.
+///
+/// The name is used to find the system's data in a scene file, so it must be
+/// unique and shouldn't change once scenes have been saved with it.
+pub trait System: Any {
+    fn name(&self) -> &'static str;
+
+    /// Reads data written by save(). `entities` maps the entity ids in the
+    /// file to the entities created for this scene.
+    fn load(&mut self, input: &mut Reader, entities: &[Entity]);
+    fn save(&self, output: &mut Writer);
+
+    /// Removes the components of destroyed entities.
+    fn handle_destroyed(&mut self, entities: &[Entity], entity_manager: &mut EntityManager);
+
+    fn exists(&self, entity: Entity) -> bool;
+    fn count(&self) -> usize;
+
+    /// Called once per frame by `Scene::update()`.
+    fn update(&mut self) { }
+
+    //Needed to get the concrete system back out of the registry
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+}
@@ -1,578 +1,2345 @@
-use std::collections::HashMap;
+use std::any::Any;
+use std::fmt;
+use scene::change::{ChangeTracker, ChangedIterator, Tick};
 use scene::entity::Entity;
 use scene::entity_manager::EntityManager;
-use scene::entity_instance::EntityInstance;
-use cgmath::{Vector3, Quaternion};
-
-pub struct TransformSystem {
-    map: HashMap<Entity, EntityInstance>,
+use scene::entity_instance::{EntityInstance, Instance};
+use scene::query::Join;
+use scene::system::System;
+use cgmath::{Vector3, Quaternion, Matrix4, Rad};
+
+/// Errors from structural edits to the transform hierarchy.
+#[derive(Copy, Debug, Eq, PartialEq)]
+pub enum HierarchyError {
+    /// The new parent is the instance itself or one of its descendants.
+    Cycle,
+    /// The sibling index is past the end of the child list.
+    IndexOutOfRange,
+    /// Sibling order was requested for a root instance. Roots aren't kept in
+    /// a sibling list.
+    NoParent,
+}
 
-    entities: Vec<Entity>,
+/// A broken invariant found by TransformSystem::validate().
+#[derive(Debug, PartialEq)]
+pub struct ValidationError {
+    pub instance: EntityInstance,
+    pub message: String,
+}
 
-    local_positions: Vec<Vector3<f32>>,
-    local_rotations: Vec<Quaternion<f32>>,
-    local_scales: Vec<f32>,
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "instance {}: {}", self.instance.index, self.message)
+    }
+}
 
-    world_positions: Vec<Vector3<f32>>,
-    world_rotations: Vec<Quaternion<f32>>,
-    world_scales: Vec<f32>,
+fn fault(idx: usize, message: String) -> ValidationError {
+    ValidationError {
+        instance: EntityInstance::new(idx as u32),
+        message: message,
+    }
+}
 
-    parents: Vec<EntityInstance>,
-    first_children: Vec<EntityInstance>,
-    next_siblings: Vec<EntityInstance>,
-    prev_siblings: Vec<EntityInstance>,
+/// How changes to local transforms reach the world transforms.
+#[derive(Copy, Debug, Eq, PartialEq)]
+pub enum UpdateMode {
+    /// Every change updates the world transform of the subtree right away.
+    Immediate,
+    /// Changes only mark the instance dirty. The world transforms of dirty
+    /// subtrees are rebuilt once by update().
+    Deferred,
 }
 
-impl TransformSystem {
-    pub fn new() -> TransformSystem {
-        TransformSystem {
-            map: HashMap::new(),
+/// How instances are ordered in the component arrays.
+#[derive(Copy, Debug, Eq, PartialEq)]
+pub enum Layout {
+    /// Creation order. destroy() moves the last instance into the hole.
+    Unordered,
+    /// Every parent comes before its children and each subtree is stored
+    /// contiguously, so update() can rebuild the world transforms with a
+    /// single linear sweep.
+    ///
+    /// Destroying instances and changing parents break the order.
+    /// The next update() then re-sorts the arrays, which moves every
+    /// instance. is_sort_pending() tells whether that will happen. Either
+    /// way, fetch instances again after update().
+    HierarchySorted,
+}
 
-            entities: Vec::new(),
+/// The axes a translation or rotation is expressed in.
+#[derive(Copy, Debug, Eq, PartialEq)]
+pub enum Space {
+    /// The instance's own axes.
+    Local,
+    World,
+}
 
-            local_positions: Vec::new(),
-            local_rotations: Vec::new(),
-            local_scales: Vec::new(),
+/// What destroy() does with the children of the destroyed instance.
+#[derive(Copy, Debug, Eq, PartialEq)]
+pub enum DestroyPolicy {
+    /// Destroys every descendant entity through the EntityManager, so all
+    /// systems see them in poll_destroyed().
+    Cascade,
+    /// Children become roots and keep their world transform.
+    Orphan,
+    /// Children take the place of the destroyed instance under its parent
+    /// and keep their world transform.
+    ReparentToGrandparent,
+}
 
-            world_positions: Vec::new(),
-            world_rotations: Vec::new(),
-            world_scales: Vec::new(),
+/// Where reparent() links the instance into the new sibling list.
+#[derive(Copy)]
+enum SiblingPosition {
+    Last,
+    Index(usize),
+    Before(EntityInstance),
+    After(EntityInstance),
+}
 
-            parents: Vec::new(),
-            first_children: Vec::new(),
-            next_siblings: Vec::new(),
-            prev_siblings: Vec::new(),
+soa_storage! {
+    /// The component columns of TransformSystem.
+    struct TransformData {
+        saved {
+            local_positions: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0),
+            local_rotations: Quaternion<f32> = Quaternion::identity(),
+            local_scales: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0),
+
+            world_positions: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0),
+            world_rotations: Quaternion<f32> = Quaternion::identity(),
+            world_scales: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0),
+
+            parents: EntityInstance = EntityInstance::none(),
+            first_children: EntityInstance = EntityInstance::none(),
+            next_siblings: EntityInstance = EntityInstance::none(),
+            prev_siblings: EntityInstance = EntityInstance::none(),
+        }
+        transient {
+            //Built from the world position, rotation and scale
+            world_matrices: Matrix4<f32> = Matrix4::identity(),
+            //The world transform of the instance and its subtree is out of
+            //date. Only used in UpdateMode::Deferred.
+            dirty: bool = false,
+
+            //When the local transform, world transform and parent or sibling
+            //order last changed
+            local_ticks: Tick = Tick(0),
+            world_ticks: Tick = Tick(0),
+            parent_ticks: Tick = Tick(0),
         }
     }
+}
 
-    pub fn load(&mut self, input: &mut Reader, id_map: &[Entity]) {
-        //Reserve space
-        let length = input.read_le_u32().ok().unwrap() as usize;
-        self.entities.reserve(length);
-        self.local_positions.reserve(length);
-        self.local_rotations.reserve(length);
-        self.local_scales.reserve(length);
+/// An instance of TransformSystem. Only valid until the next update().
+pub type TransformInstance = Instance<TransformSystem>;
 
-        self.world_positions.reserve(length);
-        self.world_rotations.reserve(length);
-        self.world_scales.reserve(length);
+pub struct TransformSystem {
+    data: TransformData,
+    //Bumped whenever instances may have moved. Handed out instances are
+    //checked against it in debug builds.
+    frame: u32,
+    changes: ChangeTracker,
 
-        self.parents.reserve(length);
-        self.first_children.reserve(length);
-        self.next_siblings.reserve(length);
-        self.prev_siblings.reserve(length);
+    update_mode: UpdateMode,
 
+    layout: Layout,
+    //The arrays need to be sorted again to match the layout
+    layout_dirty: bool,
 
-        //Read values
-        for i in 0..length as u32 {
-            let idx = input.read_le_u32().ok().unwrap();
-            let en = id_map[idx as usize];
-            self.entities.push(en);
-            self.map.insert(en, EntityInstance::new(i));
-        }
+    destroy_policy: DestroyPolicy,
+}
 
-        for i in 0..length {
-            self.local_positions.push(Vector3::new(
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap()));
-        }
-        for i in 0..length {
-            self.local_rotations.push(Quaternion::new(
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap()));
-        }
-        for i in 0..length {
-            self.local_scales.push(input.read_le_f32().ok().unwrap());
-        }
+impl TransformSystem {
+    pub fn new() -> TransformSystem {
+        TransformSystem {
+            data: TransformData::new(),
+            frame: 0,
+            changes: ChangeTracker::new(),
 
-        for i in 0..length {
-            self.world_positions.push(Vector3::new(
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap()));
-        }
-        for i in 0..length {
-            self.world_rotations.push(Quaternion::new(
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap(),
-                input.read_le_f32().ok().unwrap()));
-        }
-        for i in 0..length {
-            self.world_scales.push(input.read_le_f32().ok().unwrap());
-        }
+            update_mode: UpdateMode::Immediate,
 
-        for i in 0..length {
-            let idx = input.read_le_u32().ok().unwrap();
-            self.parents.push(EntityInstance::new(idx));
-        }
-        for i in 0..length {
-            let idx = input.read_le_u32().ok().unwrap();
-            self.first_children.push(EntityInstance::new(idx));
-        }
-        for i in 0..length {
-            let idx = input.read_le_u32().ok().unwrap();
-            self.next_siblings.push(EntityInstance::new(idx));
-        }
-        for i in 0..length {
-            let idx = input.read_le_u32().ok().unwrap();
-            self.prev_siblings.push(EntityInstance::new(idx));
+            layout: Layout::Unordered,
+            layout_dirty: false,
+
+            destroy_policy: DestroyPolicy::Cascade,
         }
     }
 
-    pub fn save(&self, output: &mut Writer) {
-        output.write_le_u32(self.entities.len() as u32);
-        for en in &self.entities { output.write_le_u32(en.id); }
-
-        for pos in &self.local_positions {
-            output.write_le_f32(pos.x);
-            output.write_le_f32(pos.y);
-            output.write_le_f32(pos.z);
-        }
-        for rot in &self.local_rotations {
-            output.write_le_f32(rot.s);
-            output.write_le_f32(rot.v.x);
-            output.write_le_f32(rot.v.y);
-            output.write_le_f32(rot.v.z);
-        }
-        for scale in &self.local_scales {
-            output.write_le_f32(*scale);
+    pub fn load(&mut self, input: &mut Reader, id_map: &[Entity]) {
+        let start = self.data.len();
+        self.data.read(input, id_map);
+
+        //The links in the file count from its first row. Move them past the
+        //rows that were already here.
+        let offset = |x: &mut EntityInstance| if x.is_valid() { x.index += start as u32 };
+        for i in start..self.data.len() {
+            offset(&mut self.data.parents[i]);
+            offset(&mut self.data.first_children[i]);
+            offset(&mut self.data.next_siblings[i]);
+            offset(&mut self.data.prev_siblings[i]);
         }
 
-        for pos in &self.world_positions {
-            output.write_le_f32(pos.x);
-            output.write_le_f32(pos.y);
-            output.write_le_f32(pos.z);
+        //World matrices aren't saved, build them from the world transforms.
+        //World transforms are saved up to date, so nothing is dirty.
+        for i in start..self.data.len() {
+            self.data.world_matrices[i] = transform_matrix(
+                self.data.world_positions[i], self.data.world_rotations[i], self.data.world_scales[i]);
+            self.mark_all(i);
         }
-        for rot in &self.world_rotations {
-            output.write_le_f32(rot.s);
-            output.write_le_f32(rot.v.x);
-            output.write_le_f32(rot.v.y);
-            output.write_le_f32(rot.v.z);
-        }
-        for scale in &self.world_scales {
-            output.write_le_f32(*scale);
-        }
-        
-        for id in &self.parents { output.write_le_u32(id.index); }
-        for id in &self.first_children { output.write_le_u32(id.index); }
-        for id in &self.next_siblings { output.write_le_u32(id.index); }
-        for id in &self.prev_siblings { output.write_le_u32(id.index); }
+
+        self.debug_validate();
+    }
+
+    pub fn save(&self, output: &mut Writer) {
+        self.data.write(output);
     }
 
     pub fn exists(&self, entity: Entity) -> bool {
-        self.map.contains_key(&entity)
+        self.data.map.contains_key(&entity)
     }
 
     /// Adds a transform component to an entity.
     ///
     /// Returns the instance.
-    pub fn create(&mut self, entity: Entity) -> EntityInstance {
-        let index = self.local_positions.len() as u32;
-
-        //Add component values
-        self.entities.push(entity);
-
-        self.local_positions.push(Vector3::new(0.0, 0.0, 0.0));
-        self.local_rotations.push(Quaternion::identity());
-        self.local_scales.push(1.0);
-
-        self.world_positions.push(Vector3::new(0.0, 0.0, 0.0));
-        self.world_rotations.push(Quaternion::identity());
-        self.world_scales.push(1.0);
-
-        self.parents.push(EntityInstance::none());
-        self.first_children.push(EntityInstance::none());
-        self.next_siblings.push(EntityInstance::none());
-        self.prev_siblings.push(EntityInstance::none());
-
-        let instance = EntityInstance::new(index);
-        self.map.insert(entity, instance);
-
-        instance
+    pub fn create(&mut self, entity: Entity) -> TransformInstance {
+        let instance = self.data.push(entity);
+        self.mark_all(instance.idx());
+        self.stamp(instance)
     }
 
-    pub fn create_or_get_instance(&mut self, en: Entity) -> EntityInstance {
+    pub fn create_or_get_instance(&mut self, en: Entity) -> TransformInstance {
         if self.exists(en) { self.get_instance(en) }
         else { self.create(en) }
     }
 
-    pub fn destroy(&mut self, entity: Entity) {
-        //Remove children
-        {
-            let parent_index = self.map[entity].idx();
-
-            let mut child = self.first_children[parent_index];
-
-            while child.is_valid() {
-                let next = self.next_siblings[child.idx()];
+    pub fn get_destroy_policy(&self) -> DestroyPolicy {
+        self.destroy_policy
+    }
 
-                let child_entity = self.get_entity(child);
-                self.destroy(child_entity);
+    pub fn set_destroy_policy(&mut self, policy: DestroyPolicy) {
+        self.destroy_policy = policy;
+    }
 
-                child = next;
+    /// Removes the transform component of an entity. The children are
+    /// handled according to the destroy policy.
+    pub fn destroy(&mut self, entity: Entity, entity_manager: &mut EntityManager) {
+        let instance = self.data.map[entity];
+
+        match self.destroy_policy {
+            DestroyPolicy::Cascade => {
+                //Children come after their parents, so going backwards removes
+                //the leaves first
+                let descendants: Vec<Entity> = self.iter_descendants(self.stamp(instance))
+                    .map(|x| self.get_entity(x.untyped())).collect();
+                for en in descendants.iter().rev() {
+                    if entity_manager.alive(*en) {
+                        entity_manager.destroy(*en);
+                    }
+                    self.remove(*en);
+                }
+            }
+            DestroyPolicy::Orphan => {
+                for child in self.get_child_entities(self.stamp(instance)) {
+                    let child = self.data.map[child];
+                    self.reparent(child, EntityInstance::none(), SiblingPosition::Last, true).unwrap();
+                }
+            }
+            DestroyPolicy::ReparentToGrandparent => {
+                //Slot the children in where the destroyed instance was
+                let grandparent = self.data.parents[instance.idx()];
+                for child in self.get_child_entities(self.stamp(instance)) {
+                    let child = self.data.map[child];
+                    let position = if grandparent.is_valid() {
+                        SiblingPosition::Before(instance)
+                    }
+                    else {
+                        SiblingPosition::Last
+                    };
+                    self.reparent(child, grandparent, position, true).unwrap();
+                }
             }
         }
 
+        self.remove(entity);
+        self.debug_validate();
+    }
 
-        let instance = self.map[entity];
-        let index = instance.idx();
-
-        let last_index = self.local_positions.len() - 1;
-        let last_entity = self.entities[last_index];
+    /// Removes a single transform. It must not have any children left.
+    fn remove(&mut self, entity: Entity) {
+        let instance = self.data.map[entity];
+        let last_instance = EntityInstance::new(self.data.len() as u32 - 1);
 
         //Remove references to instance
         self.remove_instance(instance);
 
-        //Copy last to removed
-        self.move_instance(EntityInstance::new(last_index as u32), instance);
-
-        //Remove last
-        self.entities.pop();
-        self.local_positions.pop();
-        self.local_rotations.pop();
-        self.local_scales.pop();
-        self.world_positions.pop();
-        self.world_rotations.pop();
-        self.world_scales.pop();
-        self.parents.pop();
-        self.first_children.pop();
-        self.next_siblings.pop();
-        self.prev_siblings.pop();
-
-        //Update keys in the map
-        self.map.insert(last_entity, instance);
-        self.map.remove(&entity);
+        //The last instance is about to be moved into the hole
+        if instance != last_instance {
+            self.redirect_links(last_instance, instance);
+            //It was moved out of order
+            self.layout_dirty = true;
+        }
+        self.data.swap_remove(instance);
+        self.frame += 1;
     }
 
     fn remove_instance(&mut self, instance: EntityInstance) {
         let index = instance.idx();
 
         //Update other references to removed
-        let parent_instance = self.parents[index];
-        let prev_sibling = self.prev_siblings[index];
-        let next_sibling = self.next_siblings[index];
+        let parent_instance = self.data.parents[index];
+        let prev_sibling = self.data.prev_siblings[index];
+        let next_sibling = self.data.next_siblings[index];
 
         //Update the parent if we're the first child
-        if parent_instance.is_valid() && self.first_children[parent_instance.idx()] == instance {
-            self.first_children[parent_instance.idx()] = next_sibling;
+        if parent_instance.is_valid() && self.data.first_children[parent_instance.idx()] == instance {
+            self.data.first_children[parent_instance.idx()] = next_sibling;
         }
         //Update the previous sibling to point to the next
         if prev_sibling.is_valid() {
-            self.next_siblings[prev_sibling.idx()] = next_sibling;
+            self.data.next_siblings[prev_sibling.idx()] = next_sibling;
         }
         //Update the next sibling to point to the previous
         if next_sibling.is_valid() {
-            self.prev_siblings[next_sibling.idx()] = prev_sibling;
+            self.data.prev_siblings[next_sibling.idx()] = prev_sibling;
         }
     }
 
-    fn move_instance(&mut self, src_instance: EntityInstance, dst_instance: EntityInstance) {
+    /// Points every link to `src_instance` at `dst_instance`.
+    fn redirect_links(&mut self, src_instance: EntityInstance, dst_instance: EntityInstance) {
         let src_index = src_instance.idx();
-        let dst_index = dst_instance.idx();
-
-        //Copy source to destination
-        {
-            self.entities[dst_index] = self.entities[src_index];
-            self.parents[dst_index] = self.parents[src_index];
-            self.local_positions[dst_index] = self.local_positions[src_index];
-            self.local_rotations[dst_index] = self.local_rotations[src_index];
-            self.local_scales[dst_index] = self.local_scales[src_index];
-            self.world_positions[dst_index] = self.world_positions[src_index];
-            self.world_rotations[dst_index] = self.world_rotations[src_index];
-            self.world_scales[dst_index] = self.world_scales[src_index];
-            self.first_children[dst_index] = self.first_children[src_index];
-            self.next_siblings[dst_index] = self.next_siblings[src_index];
-            self.prev_siblings[dst_index] = self.prev_siblings[src_index];
-        }
 
         //Update other references to source
-        {
-            let parent_instance = self.parents[src_index];
-            let prev_sibling = self.prev_siblings[src_index];
-            let next_sibling = self.next_siblings[src_index];
+        let parent_instance = self.data.parents[src_index];
+        let prev_sibling = self.data.prev_siblings[src_index];
+        let next_sibling = self.data.next_siblings[src_index];
+
+        //Update the parent if we're the first child
+        if parent_instance.is_valid() && self.data.first_children[parent_instance.idx()] == src_instance {
+            self.data.first_children[parent_instance.idx()] = dst_instance;
+        }
+        //Update the previous sibling to point to the next
+        if prev_sibling.is_valid() {
+            self.data.next_siblings[prev_sibling.idx()] = dst_instance;
+        }
+        //Update the next sibling to point to the previous
+        if next_sibling.is_valid() {
+            self.data.prev_siblings[next_sibling.idx()] = dst_instance;
+        }
+        //Update the children to point to the new parent
+        let mut child = self.data.first_children[src_index];
+        while child.is_valid() {
+            self.data.parents[child.idx()] = dst_instance;
+            child = self.data.next_siblings[child.idx()];
+        }
+    }
+
+    /// Checks every hierarchy link and the entity map. Returns all the
+    /// problems found.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        let count = self.data.len();
+
+        let lengths = self.data.column_lengths();
+        if lengths.iter().any(|x| *x != count) || self.data.map.len() != count {
+            errors.push(fault(0, format!(
+                "array lengths {:?} and map length {} don't match {} entities",
+                lengths, self.data.map.len(), count)));
+            return Err(errors);
+        }
+
+        let in_range = |inst: EntityInstance| !inst.is_valid() || inst.idx() < count;
+        let mut links_in_range = true;
+
+        for idx in 0..count {
+            let inst = EntityInstance::new(idx as u32);
+            let entity = self.data.entities[idx];
+            let parent = self.data.parents[idx];
+            let first_child = self.data.first_children[idx];
+            let next = self.data.next_siblings[idx];
+            let prev = self.data.prev_siblings[idx];
+
+            match self.data.map.get(&entity) {
+                Some(mapped) if *mapped == inst => { }
+                Some(mapped) => errors.push(fault(idx, format!(
+                    "map points {:?} at instance {}", entity, mapped.index))),
+                None => errors.push(fault(idx, format!(
+                    "{:?} is missing from the map", entity))),
+            }
 
-            //Update the parent if we're the first child
-            if parent_instance.is_valid() && self.first_children[parent_instance.idx()] == src_instance {
-                self.first_children[parent_instance.idx()] = dst_instance;
+            if !in_range(parent) || !in_range(first_child) || !in_range(next) || !in_range(prev) {
+                errors.push(fault(idx, format!(
+                    "link out of range (parent {}, first child {}, next {}, prev {})",
+                    parent.index, first_child.index, next.index, prev.index)));
+                links_in_range = false;
+                continue;
             }
-            //Update the previous sibling to point to the next
-            if prev_sibling.is_valid() {
-                self.next_siblings[prev_sibling.idx()] = dst_instance;
+
+            if first_child.is_valid() {
+                let child_parent = self.data.parents[first_child.idx()];
+                if child_parent != inst {
+                    errors.push(fault(idx, format!(
+                        "first child {} has parent {}", first_child.index, child_parent.index)));
+                }
+                if self.data.prev_siblings[first_child.idx()].is_valid() {
+                    errors.push(fault(idx, format!(
+                        "first child {} has a previous sibling", first_child.index)));
+                }
+            }
+            if next.is_valid() {
+                let back = self.data.prev_siblings[next.idx()];
+                if back != inst {
+                    errors.push(fault(idx, format!(
+                        "next sibling {} points back to {}", next.index, back.index)));
+                }
+                if self.data.parents[next.idx()] != parent {
+                    errors.push(fault(idx, format!(
+                        "next sibling {} has a different parent", next.index)));
+                }
+            }
+            if prev.is_valid() {
+                let forward = self.data.next_siblings[prev.idx()];
+                if forward != inst {
+                    errors.push(fault(idx, format!(
+                        "previous sibling {} points forward to {}", prev.index, forward.index)));
+                }
+                if self.data.parents[prev.idx()] != parent {
+                    errors.push(fault(idx, format!(
+                        "previous sibling {} has a different parent", prev.index)));
+                }
+            }
+
+            if parent.is_valid() {
+                let parent_first = self.data.first_children[parent.idx()];
+                if !prev.is_valid() && parent_first != inst {
+                    errors.push(fault(idx, format!(
+                        "no previous sibling but parent {} starts with {}",
+                        parent.index, parent_first.index)));
+                }
+
+                //A chain longer than the number of instances must loop
+                let mut ancestor = parent;
+                let mut steps = 0;
+                while ancestor.is_valid() && steps <= count {
+                    ancestor = self.data.parents[ancestor.idx()];
+                    steps += 1;
+                }
+                if steps > count {
+                    errors.push(fault(idx, "parent chain contains a cycle".to_string()));
+                }
+            }
+            else if next.is_valid() || prev.is_valid() {
+                errors.push(fault(idx, "root has siblings".to_string()));
+            }
+        }
+
+        //Every link can point back correctly while a group of siblings sits
+        //in a ring of its own, so walk each child list and make sure it
+        //reaches every instance that names the parent
+        if links_in_range {
+            let mut claimed: Vec<usize> = (0..count).map(|_| 0).collect();
+            for parent in self.data.parents.iter().filter(|x| x.is_valid()) {
+                claimed[parent.idx()] += 1;
+            }
+            for idx in 0..count {
+                let mut child = self.data.first_children[idx];
+                let mut reached = 0;
+                while child.is_valid() && reached <= claimed[idx] {
+                    child = self.data.next_siblings[child.idx()];
+                    reached += 1;
+                }
+                if child.is_valid() {
+                    errors.push(fault(idx, "child list contains a cycle".to_string()));
+                }
+                else if reached != claimed[idx] {
+                    errors.push(fault(idx, format!(
+                        "child list reaches {} of the {} instances with this parent",
+                        reached, claimed[idx])));
+                }
             }
-            //Update the next sibling to point to the previous
-            if next_sibling.is_valid() {
-                self.prev_siblings[next_sibling.idx()] = dst_instance;
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Runs validate() in debug builds. Called once at the end of each public
+    /// structural edit, not for every step of it, so bulk edits stay linear.
+    fn debug_validate(&self) {
+        if cfg!(not(ndebug)) {
+            if let Err(errors) = self.validate() {
+                let messages: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+                panic!("Transform hierarchy is corrupt:\n{}", messages.connect("\n"));
             }
         }
     }
 
-    pub fn handle_destroyed(&mut self, entity: &[Entity]) {
-        for entity in entity.iter() {
-            if self.map.contains_key(entity) {
-                self.destroy(*entity);
+    /// Destroys the transforms of destroyed entities. With
+    /// DestroyPolicy::Cascade, this destroys more entities, which show up in
+    /// the next poll_destroyed().
+    pub fn handle_destroyed(&mut self, entities: &[Entity], entity_manager: &mut EntityManager) {
+        for entity in entities.iter() {
+            if self.data.map.contains_key(entity) {
+                self.destroy(*entity, entity_manager);
             }
         }
     }
 
     pub fn count(&self) -> usize {
-        self.entities.len()
+        self.data.entities.len()
     }
 
 
 
-    pub fn get_instance(&self, entity: Entity) -> EntityInstance {
-        if let Some(instance) = self.map.get(&entity) {
-            *instance
+    pub fn get_instance(&self, entity: Entity) -> TransformInstance {
+        if let Some(instance) = self.data.map.get(&entity) {
+            self.stamp(*instance)
         }
         else {
-            EntityInstance::none()
+            Instance::none()
         }
     }
 
     fn get_entity(&self, instance: EntityInstance) -> Entity {
-        self.entities[instance.idx()]
+        self.data.entities[instance.idx()]
     }
 
+    /// Unwraps an instance that was handed out by this system. In debug
+    /// builds, this checks that it's from the current frame.
+    fn check(&self, instance: TransformInstance) -> EntityInstance {
+        debug_assert!(!instance.is_valid() || instance.frame() == self.frame,
+            "TransformInstance from frame {} used in frame {}", instance.frame(), self.frame);
+        instance.untyped()
+    }
 
-
-    pub fn get_local_position(&self, instance: EntityInstance) -> Vector3<f32> {
-        self.local_positions[instance.idx()]
+    fn stamp(&self, instance: EntityInstance) -> TransformInstance {
+        Instance::new(instance, self.frame)
     }
 
-    pub fn set_local_position(&mut self, instance: EntityInstance, position: Vector3<f32>) {
-        let idx = instance.idx();
-        self.local_positions[idx] = position;
 
-        //Update world position
-        let parent = self.parents[idx];
-        let (par_pos, par_rot, par_scale) = if parent.is_valid() {
-            (self.world_positions[parent.idx()],
-            self.world_rotations[parent.idx()],
-            self.world_scales[parent.idx()])
-        }
-        else {
-            (Vector3::new(0.0, 0.0, 0.0), Quaternion::identity(), 1.0)
-        };
 
-        self.update_world_transform(instance, par_pos, par_rot, par_scale);
+    /// The current change tick. It advances on every update().
+    pub fn current_tick(&self) -> Tick {
+        self.changes.tick()
     }
 
+    /// Iterates over the entities whose local transform changed at or after
+    /// `since`. Newly created transforms count as changed.
+    pub fn iter_local_changed<'a>(&'a self, since: Tick) -> ChangedIterator<'a> {
+        self.changes.changed_since(&self.data.entities, &self.data.local_ticks, since)
+    }
 
-    pub fn get_local_rotation(&self, instance: EntityInstance) -> Quaternion<f32> {
-        self.local_rotations[instance.idx()]
+    /// Iterates over the entities whose world transform was rebuilt at or
+    /// after `since`. In UpdateMode::Deferred, that happens in update().
+    pub fn iter_world_changed<'a>(&'a self, since: Tick) -> ChangedIterator<'a> {
+        self.changes.changed_since(&self.data.entities, &self.data.world_ticks, since)
     }
 
-    pub fn set_local_rotation(&mut self, instance: EntityInstance, rotation: Quaternion<f32>) {
-        let idx = instance.idx();
-        self.local_rotations[idx] = rotation;
+    /// Iterates over the entities that were moved in the hierarchy at or
+    /// after `since`, including changes to sibling order.
+    pub fn iter_parent_changed<'a>(&'a self, since: Tick) -> ChangedIterator<'a> {
+        self.changes.changed_since(&self.data.entities, &self.data.parent_ticks, since)
+    }
 
-        //Update world position
-        let parent = self.parents[idx];
-        let (par_pos, par_rot, par_scale) = if parent.is_valid() {
-            (self.world_positions[parent.idx()],
-            self.world_rotations[parent.idx()],
-            self.world_scales[parent.idx()])
-        }
-        else {
-            (Vector3::new(0.0, 0.0, 0.0), Quaternion::identity(), 1.0)
-        };
 
-        self.update_world_transform(instance, par_pos, par_rot, par_scale);
+
+    pub fn get_update_mode(&self) -> UpdateMode {
+        self.update_mode
+    }
+
+    /// Switches between immediate and deferred world transform updates.
+    /// Leaving deferred mode brings every world transform up to date.
+    pub fn set_update_mode(&mut self, mode: UpdateMode) {
+        if self.update_mode == UpdateMode::Deferred {
+            //Works in any layout and doesn't move instances
+            self.update_unordered();
+        }
+        self.update_mode = mode;
     }
 
+    pub fn get_layout(&self) -> Layout {
+        self.layout
+    }
 
-    pub fn get_local_scale(&self, instance: EntityInstance) -> f32 {
-        self.local_scales[instance.idx()]
+    /// Whether the next update() re-sorts the arrays to restore
+    /// Layout::HierarchySorted.
+    pub fn is_sort_pending(&self) -> bool {
+        self.layout == Layout::HierarchySorted && self.layout_dirty
     }
 
-    pub fn set_local_scale(&mut self, instance: EntityInstance, scale: f32) {
-        let idx = instance.idx();
-        self.local_scales[idx] = scale;
+    /// Changes how instances are stored. Switching to
+    /// Layout::HierarchySorted sorts the arrays right away, so existing
+    /// instances are invalidated.
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+        if layout == Layout::HierarchySorted {
+            self.sort_hierarchy();
+            self.debug_validate();
+        }
+    }
 
-        //Update world position
-        let parent = self.parents[idx];
-        let (par_pos, par_rot, par_scale) = if parent.is_valid() {
-            (self.world_positions[parent.idx()],
-            self.world_rotations[parent.idx()],
-            self.world_scales[parent.idx()])
+    /// Rebuilds the world transforms of all dirty subtrees. Call this once
+    /// per frame when using UpdateMode::Deferred.
+    ///
+    /// This starts a new frame, so instances fetched before are no longer
+    /// valid. With Layout::HierarchySorted, the sort order is restored first
+    /// if the hierarchy changed, which actually moves them.
+    pub fn update(&mut self) {
+        self.frame += 1;
+        self.changes.advance();
+
+        if self.layout == Layout::HierarchySorted {
+            if self.layout_dirty {
+                self.sort_hierarchy();
+                self.debug_validate();
+            }
+            self.update_sorted();
         }
         else {
-            (Vector3::new(0.0, 0.0, 0.0), Quaternion::identity(), 1.0)
-        };
+            self.update_unordered();
+        }
+    }
+
+    fn update_sorted(&mut self) {
+        if self.update_mode == UpdateMode::Immediate {
+            return;
+        }
+
+        //Parents come first, so their world transform is always up to date
+        //by the time we reach the children
+        for idx in 0..self.data.dirty.len() {
+            let parent = self.data.parents[idx];
+            if parent.is_valid() && self.data.dirty[parent.idx()] {
+                self.data.dirty[idx] = true;
+            }
+            if !self.data.dirty[idx] {
+                continue;
+            }
 
-        self.update_world_transform(instance, par_pos, par_rot, par_scale);
+            let (par_pos, par_rot, par_scale) = if parent.is_valid() {
+                let par_idx = parent.idx();
+                (self.data.world_positions[par_idx], self.data.world_rotations[par_idx], self.data.world_scales[par_idx])
+            }
+            else {
+                (Vector3::new(0.0, 0.0, 0.0), Quaternion::identity(), Vector3::new(1.0, 1.0, 1.0))
+            };
+
+            let (world_pos, world_rot, world_scale) = combine_transforms(par_pos, par_rot, par_scale,
+                self.data.local_positions[idx], self.data.local_rotations[idx], self.data.local_scales[idx]);
+            self.data.world_positions[idx] = world_pos;
+            self.data.world_rotations[idx] = world_rot;
+            self.data.world_scales[idx] = world_scale;
+            self.data.world_matrices[idx] = transform_matrix(world_pos, world_rot, world_scale);
+            self.changes.mark(&mut self.data.world_ticks, idx);
+        }
+
+        for dirty in self.data.dirty.iter_mut() {
+            *dirty = false;
+        }
     }
 
-    fn update_world_transform(&mut self, inst: EntityInstance,
-    par_pos: Vector3<f32>, par_rot: Quaternion<f32>, par_scale: f32) {
-        use cgmath::Vector;
-        let idx = inst.idx();
-        self.world_positions[idx] = par_pos + par_rot.mul_v(&self.local_positions[idx]).mul_s(par_scale);
-        self.world_rotations[idx] = par_rot.mul_q(&self.local_rotations[idx]);
-        self.world_scales[idx] = par_scale * self.local_scales[idx];
+    fn update_unordered(&mut self) {
+        for idx in 0..self.data.dirty.len() {
+            if !self.data.dirty[idx] {
+                continue;
+            }
 
-        //Update children
-        let world_pos = self.world_positions[idx];
-        let world_rot = self.world_rotations[idx];
-        let world_scale = self.world_scales[idx];
-        for child in self.get_children(inst) {
-            self.update_world_transform(child,
-                world_pos, world_rot, world_scale);
+            //A dirty ancestor will rebuild this subtree as well
+            let instance = EntityInstance::new(idx as u32);
+            if self.has_dirty_ancestor(instance) {
+                continue;
+            }
+
+            let (par_pos, par_rot, par_scale) = self.get_parent_world_transform(idx);
+            self.update_world_transform(instance, par_pos, par_rot, par_scale);
         }
     }
 
+    /// Reorders the arrays so every subtree is stored depth first and
+    /// contiguously. Roots keep their relative order.
+    fn sort_hierarchy(&mut self) {
+        let count = self.data.entities.len();
+
+        //Old index of the instance that ends up at each position
+        let mut order = Vec::with_capacity(count);
+        for idx in 0..count {
+            if !self.data.parents[idx].is_valid() {
+                let root = EntityInstance::new(idx as u32);
+                order.push(idx);
+                order.extend(self.iter_descendants(self.stamp(root)).map(|x| x.idx()));
+            }
+        }
+        assert!(order.len() == count);
+
+        //New index of each instance
+        let mut new_indices: Vec<EntityInstance> = (0..count).map(|_| EntityInstance::none()).collect();
+        for (new_idx, old_idx) in order.iter().enumerate() {
+            new_indices[*old_idx] = EntityInstance::new(new_idx as u32);
+        }
 
+        self.data.permute(&order);
+        self.data.parents = remap(&self.data.parents, &new_indices);
+        self.data.first_children = remap(&self.data.first_children, &new_indices);
+        self.data.next_siblings = remap(&self.data.next_siblings, &new_indices);
+        self.data.prev_siblings = remap(&self.data.prev_siblings, &new_indices);
 
-    pub fn get_world_position(&self, instance: EntityInstance) -> Vector3<f32> {
-        self.world_positions[instance.idx()]
+        self.layout_dirty = false;
+        self.frame += 1;
     }
 
-    pub fn set_world_position(&mut self, instance: EntityInstance, position: Vector3<f32>) {
-        //TODO: Update local position + children
-        self.world_positions[instance.idx()] = position;
+    fn has_dirty_ancestor(&self, instance: EntityInstance) -> bool {
+        self.iter_ancestors(self.stamp(instance)).any(|x| self.data.dirty[x.idx()])
     }
 
-    pub fn get_world_rotation(&self, instance: EntityInstance) -> Quaternion<f32> {
-        self.world_rotations[instance.idx()]
+    /// Called after the local transform of an instance changes.
+    fn local_changed(&mut self, instance: EntityInstance) {
+        self.changes.mark(&mut self.data.local_ticks, instance.idx());
+        self.transform_changed(instance);
     }
 
-    pub fn set_world_rotation(&mut self, instance: EntityInstance, rotation: Quaternion<f32>) {
-        //TODO: Update local rotation + children
-        self.world_rotations[instance.idx()] = rotation;
+    fn mark_all(&mut self, idx: usize) {
+        self.changes.mark(&mut self.data.local_ticks, idx);
+        self.changes.mark(&mut self.data.world_ticks, idx);
+        self.changes.mark(&mut self.data.parent_ticks, idx);
     }
 
-    pub fn get_world_scale(&self, instance: EntityInstance) -> f32 {
-        self.world_scales[instance.idx()]
+    /// Called after the local transform or the parent of an instance changes.
+    fn transform_changed(&mut self, instance: EntityInstance) {
+        match self.update_mode {
+            UpdateMode::Immediate => {
+                let (par_pos, par_rot, par_scale) = self.get_parent_world_transform(instance.idx());
+                self.update_world_transform(instance, par_pos, par_rot, par_scale);
+            }
+            UpdateMode::Deferred => {
+                self.data.dirty[instance.idx()] = true;
+            }
+        }
     }
 
-    pub fn set_world_scale(&mut self, instance: EntityInstance, scale: f32) {
-        //TODO: Update local scale + children
-        self.world_scales[instance.idx()] = scale;
+
+
+    pub fn get_local_position(&self, instance: TransformInstance) -> Vector3<f32> {
+        let instance = self.check(instance);
+        self.data.local_positions[instance.idx()]
     }
 
+    pub fn set_local_position(&mut self, instance: TransformInstance, position: Vector3<f32>) {
+        let instance = self.check(instance);
+        self.data.local_positions[instance.idx()] = position;
+        self.local_changed(instance);
+    }
 
 
-    pub fn get_parent(&self, instance: EntityInstance) -> EntityInstance {
-        self.parents[instance.idx()]
+    pub fn get_local_rotation(&self, instance: TransformInstance) -> Quaternion<f32> {
+        let instance = self.check(instance);
+        self.data.local_rotations[instance.idx()]
     }
 
-    pub fn set_parent(&mut self, child: EntityInstance, parent: EntityInstance) {
-        //TODO: Update the old parent and siblings
+    pub fn set_local_rotation(&mut self, instance: TransformInstance, rotation: Quaternion<f32>) {
+        let instance = self.check(instance);
+        self.data.local_rotations[instance.idx()] = rotation;
+        self.local_changed(instance);
+    }
 
-        //Set the parent of the child
-        self.parents[child.idx()] = parent;
 
-        //Update the parent
-        let old_child = self.first_children[parent.idx()];
-        self.first_children[parent.idx()] = child;
-        self.next_siblings[child.idx()] = old_child;
-        if old_child.is_valid() {
-            self.prev_siblings[old_child.idx()] = child;
-        }
+    pub fn get_local_scale(&self, instance: TransformInstance) -> Vector3<f32> {
+        let instance = self.check(instance);
+        self.data.local_scales[instance.idx()]
     }
 
-    pub fn get_first_child(&self, instance: EntityInstance) -> EntityInstance {
-        self.first_children[instance.idx()]
+    pub fn set_local_scale(&mut self, instance: TransformInstance, scale: Vector3<f32>) {
+        let instance = self.check(instance);
+        self.data.local_scales[instance.idx()] = scale;
+        self.local_changed(instance);
     }
 
-    pub fn get_next_sibling(&self, instance: EntityInstance) -> EntityInstance {
-        self.next_siblings[instance.idx()]
+    /// Returns the world position, rotation and scale of the parent. Root
+    /// instances get the identity transform.
+    fn get_parent_world_transform(&self, idx: usize) -> (Vector3<f32>, Quaternion<f32>, Vector3<f32>) {
+        self.get_world_transform(self.data.parents[idx])
     }
 
-    pub fn get_prev_sibling(&self, instance: EntityInstance) -> EntityInstance {
-        self.prev_siblings[instance.idx()]
+    /// Returns the world position, rotation and scale of an instance, or the
+    /// identity transform for `EntityInstance::none()`.
+    ///
+    /// If the stored world transform is out of date, it is recomputed from
+    /// the local transforms without being written back.
+    fn get_world_transform(&self, instance: EntityInstance) -> (Vector3<f32>, Quaternion<f32>, Vector3<f32>) {
+        if !instance.is_valid() {
+            return (Vector3::new(0.0, 0.0, 0.0), Quaternion::identity(), Vector3::new(1.0, 1.0, 1.0));
+        }
+
+        let idx = instance.idx();
+        if self.update_mode == UpdateMode::Deferred &&
+        (self.data.dirty[idx] || self.has_dirty_ancestor(instance)) {
+            let (par_pos, par_rot, par_scale) = self.compute_world_transform(self.data.parents[idx]);
+            combine_transforms(par_pos, par_rot, par_scale,
+                self.data.local_positions[idx], self.data.local_rotations[idx], self.data.local_scales[idx])
+        }
+        else {
+            (self.data.world_positions[idx], self.data.world_rotations[idx], self.data.world_scales[idx])
+        }
     }
 
+    /// Builds the world transform from the local transforms of the instance
+    /// and all of its ancestors.
+    fn compute_world_transform(&self, instance: EntityInstance) -> (Vector3<f32>, Quaternion<f32>, Vector3<f32>) {
+        if !instance.is_valid() {
+            return (Vector3::new(0.0, 0.0, 0.0), Quaternion::identity(), Vector3::new(1.0, 1.0, 1.0));
+        }
 
+        let idx = instance.idx();
+        let (par_pos, par_rot, par_scale) = self.compute_world_transform(self.data.parents[idx]);
+        combine_transforms(par_pos, par_rot, par_scale,
+            self.data.local_positions[idx], self.data.local_rotations[idx], self.data.local_scales[idx])
+    }
 
-    pub fn iter_children<'a>(&'a self, parent: EntityInstance) -> ChildIterator<'a> {
-        ChildIterator {
-            next_siblings: &self.next_siblings,
-            current: self.first_children[parent.idx()],
+    fn update_world_transform(&mut self, inst: EntityInstance,
+    par_pos: Vector3<f32>, par_rot: Quaternion<f32>, par_scale: Vector3<f32>) {
+        let idx = inst.idx();
+        let (world_pos, world_rot, world_scale) = combine_transforms(par_pos, par_rot, par_scale,
+            self.data.local_positions[idx], self.data.local_rotations[idx], self.data.local_scales[idx]);
+        self.data.world_positions[idx] = world_pos;
+        self.data.world_rotations[idx] = world_rot;
+        self.data.world_scales[idx] = world_scale;
+        self.data.world_matrices[idx] = transform_matrix(world_pos, world_rot, world_scale);
+        self.data.dirty[idx] = false;
+        self.changes.mark(&mut self.data.world_ticks, idx);
+
+        //Update children
+        let mut child = self.data.first_children[idx];
+        while child.is_valid() {
+            self.update_world_transform(child, world_pos, world_rot, world_scale);
+            child = self.data.next_siblings[child.idx()];
         }
     }
 
-    pub fn get_children(&self, parent: EntityInstance) -> Vec<EntityInstance> {
-        self.iter_children(parent).collect()
+
+
+    pub fn get_world_position(&self, instance: TransformInstance) -> Vector3<f32> {
+        let instance = self.check(instance);
+        self.get_world_transform(instance).0
     }
 
-    pub fn get_child_entities(&self, parent: EntityInstance) -> Vec<Entity> {
-        self.iter_children(parent).map(|x| self.get_entity(x)).collect()
+    pub fn set_world_position(&mut self, instance: TransformInstance, position: Vector3<f32>) {
+        let instance = self.check(instance);
+        use cgmath::Vector;
+        let idx = instance.idx();
+        let (par_pos, par_rot, par_scale) = self.get_parent_world_transform(idx);
+
+        //Solve for the local position (inverse of combine_transforms)
+        self.data.local_positions[idx] = par_rot.conjugate()
+            .mul_v(&(position - par_pos))
+            .div_v(&par_scale);
+
+        self.local_changed(instance);
     }
-}
 
+    pub fn get_world_rotation(&self, instance: TransformInstance) -> Quaternion<f32> {
+        let instance = self.check(instance);
+        self.get_world_transform(instance).1
+    }
 
+    pub fn set_world_rotation(&mut self, instance: TransformInstance, rotation: Quaternion<f32>) {
+        let instance = self.check(instance);
+        let idx = instance.idx();
+        let (_, par_rot, _) = self.get_parent_world_transform(idx);
 
-pub struct ChildIterator<'a> {
-    next_siblings: &'a Vec<EntityInstance>,
-    current: EntityInstance,
-}
+        //Solve for the local rotation
+        self.data.local_rotations[idx] = par_rot.conjugate().mul_q(&rotation);
 
-impl<'a> Iterator for ChildIterator<'a> {
-    type Item = EntityInstance;
-    fn next(&mut self) -> Option<EntityInstance> {
-        if self.current.is_valid() {
-            let output = self.current;
+        self.local_changed(instance);
+    }
 
-            //Advance to next sibling
-            self.current = self.next_siblings[output.idx()];
+    pub fn get_world_scale(&self, instance: TransformInstance) -> Vector3<f32> {
+        let instance = self.check(instance);
+        self.get_world_transform(instance).2
+    }
 
-            Some(output)
+    pub fn set_world_scale(&mut self, instance: TransformInstance, scale: Vector3<f32>) {
+        let instance = self.check(instance);
+        use cgmath::Vector;
+        let idx = instance.idx();
+        let (_, _, par_scale) = self.get_parent_world_transform(idx);
+
+        //Solve for the local scale
+        self.data.local_scales[idx] = scale.div_v(&par_scale);
+
+        self.local_changed(instance);
+    }
+
+    /// Returns the matrix that takes points from the local space of the
+    /// instance to world space.
+    ///
+    /// The hierarchy only stores position, rotation and scale, so any shear
+    /// from rotating a child under a non-uniformly scaled parent is lost.
+    pub fn get_world_matrix(&self, instance: TransformInstance) -> Matrix4<f32> {
+        let instance = self.check(instance);
+        let idx = instance.idx();
+        if self.update_mode == UpdateMode::Deferred &&
+        (self.data.dirty[idx] || self.has_dirty_ancestor(instance)) {
+            let (world_pos, world_rot, world_scale) = self.get_world_transform(instance);
+            transform_matrix(world_pos, world_rot, world_scale)
         }
         else {
-            None
+            self.data.world_matrices[idx]
         }
     }
-}
 
-#[test]
-fn iter_children_test() {
-    let mut em = EntityManager::new();
-    let mut tr = TransformSystem::new();
 
-    let e1 = em.create();
-    let e2 = em.create();
-    let e3 = em.create();
-    let i1 = tr.create(e1);
-    let i2 = tr.create(e2);
-    let i3 = tr.create(e3);
-    tr.set_parent(i1, i2);
-    tr.set_parent(i3, i2);
 
-    let children: Vec<EntityInstance> = tr.iter_children(i2).collect();
-    assert_eq!(children, vec![i3, i1]);
-    let children: Vec<EntityInstance> = tr.iter_children(i1).collect();
-    assert_eq!(children, Vec::new());
+    /// Moves an instance by `delta`. With Space::Local, `delta` is along the
+    /// instance's own axes and isn't affected by its scale.
+    pub fn translate(&mut self, instance: TransformInstance, delta: Vector3<f32>, space: Space) {
+        let (world_pos, world_rot, _) = self.get_world_transform(self.check(instance));
+        let delta = match space {
+            Space::Local => world_rot.mul_v(&delta),
+            Space::World => delta,
+        };
+        self.set_world_position(instance, world_pos + delta);
+    }
 
-    for inst in tr.get_children(i2) {
-        tr.set_local_position(inst, Vector3::new(1.0, 2.0, 3.0));
+    /// Applies `rotation` on top of the current rotation. With Space::Local,
+    /// the rotation is about the instance's own axes.
+    pub fn rotate(&mut self, instance: TransformInstance, rotation: Quaternion<f32>, space: Space) {
+        match space {
+            Space::Local => {
+                let local_rot = self.data.local_rotations[self.check(instance).idx()];
+                self.set_local_rotation(instance, local_rot.mul_q(&rotation));
+            }
+            Space::World => {
+                let (_, world_rot, _) = self.get_world_transform(self.check(instance));
+                self.set_world_rotation(instance, rotation.mul_q(&world_rot));
+            }
+        }
     }
 
-    assert_eq!(tr.get_child_entities(i2), vec![e3, e1]);
-}
+    /// Turns an instance so that its local +Z axis points at `target` and its
+    /// local +Y axis is as close to `up` as possible. Both are in world
+    /// space. If `up` points along the target, world +Z (or +X) is used
+    /// instead.
+    pub fn look_at(&mut self, instance: TransformInstance, target: Vector3<f32>, up: Vector3<f32>) {
+        use cgmath::Rotation;
+        let (world_pos, _, _) = self.get_world_transform(self.check(instance));
+        let dir = target - world_pos;
+        if dir == Vector3::new(0.0, 0.0, 0.0) {
+            return;
+        }
 
-#[test]
-fn set_local_test() {
-    use cgmath::{ApproxEq, Rotation3};
-    let mut em = EntityManager::new();
-    let mut tr = TransformSystem::new();
+        //An up along `dir` leaves the roll undefined and cgmath would divide
+        //by zero. Fall back to another axis, at least one of which works.
+        let dot = |a: Vector3<f32>, b: Vector3<f32>| a.x * b.x + a.y * b.y + a.z * b.z;
+        let usable = |up: &Vector3<f32>| {
+            let side = dir.cross(up);
+            dot(side, side) > 1.0e-6 * dot(dir, dir) * dot(*up, *up)
+        };
+        let up = [up, Vector3::new(0.0, 0.0, 1.0), Vector3::new(1.0, 0.0, 0.0)]
+            .iter().map(|x| *x).find(|x| usable(x)).unwrap();
 
-    let e1 = em.create();
-    let e2 = em.create();
-    let i1 = tr.create(e1);
-    let i2 = tr.create(e2);
+        //cgmath builds the view rotation, which turns `dir` onto +Z. The
+        //instance needs the opposite, turning its +Z onto `dir`.
+        let rotation: Quaternion<f32> = Rotation::look_at(&dir, &up);
+        self.set_world_rotation(instance, rotation.conjugate());
+    }
 
-    tr.set_parent(i2, i1);
-    tr.set_local_position(i1, Vector3::new(1.0, 0.0, 1.0));
-    tr.set_local_rotation(i1, Rotation3::from_angle_y(::cgmath::Rad::turn_div_4()));
-    tr.set_local_position(i2, Vector3::new(0.0, 0.0, -1.0));
-    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(0.0, 0.0, 1.0)));
+    /// Rotates an instance about a world space axis through `pivot`. Both
+    /// the position and the rotation change.
+    pub fn rotate_around(&mut self, instance: TransformInstance,
+    pivot: Vector3<f32>, axis: Vector3<f32>, angle: Rad<f32>) {
+        let instance = self.check(instance);
+        use cgmath::{Vector, Rotation3};
+        let rotation: Quaternion<f32> = Rotation3::from_axis_angle(&axis, angle);
+
+        let idx = instance.idx();
+        let (world_pos, world_rot, _) = self.get_world_transform(instance);
+        let (par_pos, par_rot, par_scale) = self.get_parent_world_transform(idx);
+        let world_pos = pivot + rotation.mul_v(&(world_pos - pivot));
+        let world_rot = rotation.mul_q(&world_rot);
+
+        //Solve for both local values before updating the world transforms
+        let inv_rot = par_rot.conjugate();
+        self.data.local_positions[idx] = inv_rot.mul_v(&(world_pos - par_pos)).div_v(&par_scale);
+        self.data.local_rotations[idx] = inv_rot.mul_q(&world_rot);
+        self.local_changed(instance);
+    }
+
+
+
+    /// Takes a point from the local space of the instance to world space.
+    pub fn transform_point(&self, instance: TransformInstance, point: Vector3<f32>) -> Vector3<f32> {
+        let instance = self.check(instance);
+        use cgmath::Vector;
+        let (world_pos, world_rot, world_scale) = self.get_world_transform(instance);
+        world_pos + world_rot.mul_v(&point.mul_v(&world_scale))
+    }
+
+    /// Takes a vector from the local space of the instance to world space.
+    /// Vectors are rotated and scaled but not translated.
+    pub fn transform_vector(&self, instance: TransformInstance, vector: Vector3<f32>) -> Vector3<f32> {
+        let instance = self.check(instance);
+        use cgmath::Vector;
+        let (_, world_rot, world_scale) = self.get_world_transform(instance);
+        world_rot.mul_v(&vector.mul_v(&world_scale))
+    }
+
+    /// Takes a direction from the local space of the instance to world space.
+    /// Directions are only rotated, so their length is kept.
+    pub fn transform_direction(&self, instance: TransformInstance, direction: Vector3<f32>) -> Vector3<f32> {
+        let instance = self.check(instance);
+        let (_, world_rot, _) = self.get_world_transform(instance);
+        world_rot.mul_v(&direction)
+    }
+
+    /// Takes a point from world space to the local space of the instance.
+    pub fn inverse_transform_point(&self, instance: TransformInstance, point: Vector3<f32>) -> Vector3<f32> {
+        let instance = self.check(instance);
+        use cgmath::Vector;
+        let (world_pos, world_rot, world_scale) = self.get_world_transform(instance);
+        world_rot.conjugate().mul_v(&(point - world_pos)).div_v(&world_scale)
+    }
+
+    /// Takes a vector from world space to the local space of the instance.
+    pub fn inverse_transform_vector(&self, instance: TransformInstance, vector: Vector3<f32>) -> Vector3<f32> {
+        let instance = self.check(instance);
+        use cgmath::Vector;
+        let (_, world_rot, world_scale) = self.get_world_transform(instance);
+        world_rot.conjugate().mul_v(&vector).div_v(&world_scale)
+    }
+
+    /// Takes a direction from world space to the local space of the
+    /// instance, ignoring scale.
+    pub fn inverse_transform_direction(&self, instance: TransformInstance, direction: Vector3<f32>) -> Vector3<f32> {
+        let instance = self.check(instance);
+        let (_, world_rot, _) = self.get_world_transform(instance);
+        world_rot.conjugate().mul_v(&direction)
+    }
+
+    /// Returns the matrix that takes points from the local space of `from`
+    /// to the local space of `to`. Either one can be `Instance::none()`
+    /// to mean world space.
+    pub fn local_to_local(&self, from: TransformInstance, to: TransformInstance) -> Matrix4<f32> {
+        use cgmath::Matrix;
+        let (from_pos, from_rot, from_scale) = self.get_world_transform(self.check(from));
+        let (to_pos, to_rot, to_scale) = self.get_world_transform(self.check(to));
+        inverse_transform_matrix(to_pos, to_rot, to_scale)
+            .mul_m(&transform_matrix(from_pos, from_rot, from_scale))
+    }
 
-    tr.set_local_scale(i1, 2.0);
-    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(-1.0, 0.0, 1.0)));
-}
 
+
+    pub fn get_parent(&self, instance: TransformInstance) -> TransformInstance {
+        self.stamp(self.data.parents[self.check(instance).idx()])
+    }
+
+    /// Moves `child` under `parent` as its last child, keeping its local
+    /// transform. Passing `Instance::none()` as the parent makes the
+    /// child a root.
+    ///
+    /// Fails if `parent` is `child` or one of its descendants.
+    pub fn set_parent(&mut self, child: TransformInstance, parent: TransformInstance)
+    -> Result<(), HierarchyError> {
+        let (child, parent) = (self.check(child), self.check(parent));
+        self.edit_hierarchy(child, parent, SiblingPosition::Last, false)
+    }
+
+    /// Moves `child` under `parent`, recomputing the local transform so that
+    /// the world transform doesn't change.
+    pub fn set_parent_keep_world(&mut self, child: TransformInstance, parent: TransformInstance)
+    -> Result<(), HierarchyError> {
+        let (child, parent) = (self.check(child), self.check(parent));
+        self.edit_hierarchy(child, parent, SiblingPosition::Last, true)
+    }
+
+    /// Moves `child` under `parent` so that it ends up at position `index`
+    /// among the children of `parent`.
+    pub fn insert_child_at(&mut self, parent: TransformInstance, child: TransformInstance, index: usize)
+    -> Result<(), HierarchyError> {
+        let (child, parent) = (self.check(child), self.check(parent));
+        self.edit_hierarchy(child, parent, SiblingPosition::Index(index), false)
+    }
+
+    /// Moves `instance` in front of `sibling`, taking the parent of `sibling`
+    /// if needed.
+    pub fn move_before(&mut self, instance: TransformInstance, sibling: TransformInstance)
+    -> Result<(), HierarchyError> {
+        let (instance, sibling) = (self.check(instance), self.check(sibling));
+        let parent = self.data.parents[sibling.idx()];
+        self.edit_hierarchy(instance, parent, SiblingPosition::Before(sibling), false)
+    }
+
+    /// Moves `instance` behind `sibling`, taking the parent of `sibling` if
+    /// needed.
+    pub fn move_after(&mut self, instance: TransformInstance, sibling: TransformInstance)
+    -> Result<(), HierarchyError> {
+        let (instance, sibling) = (self.check(instance), self.check(sibling));
+        let parent = self.data.parents[sibling.idx()];
+        self.edit_hierarchy(instance, parent, SiblingPosition::After(sibling), false)
+    }
+
+    /// Returns the position of `instance` among its siblings.
+    pub fn get_sibling_index(&self, instance: TransformInstance) -> usize {
+        let instance = self.check(instance);
+        let mut index = 0;
+        let mut prev = self.data.prev_siblings[instance.idx()];
+        while prev.is_valid() {
+            index += 1;
+            prev = self.data.prev_siblings[prev.idx()];
+        }
+        index
+    }
+
+    /// Moves `instance` to position `index` among its siblings.
+    pub fn set_sibling_index(&mut self, instance: TransformInstance, index: usize)
+    -> Result<(), HierarchyError> {
+        let instance = self.check(instance);
+        let parent = self.data.parents[instance.idx()];
+        self.edit_hierarchy(instance, parent, SiblingPosition::Index(index), false)
+    }
+
+    /// reparent() followed by debug_validate(), for the public API.
+    fn edit_hierarchy(&mut self, child: EntityInstance, parent: EntityInstance,
+    position: SiblingPosition, keep_world: bool) -> Result<(), HierarchyError> {
+        let result = self.reparent(child, parent, position, keep_world);
+        self.debug_validate();
+        result
+    }
+
+    fn reparent(&mut self, child: EntityInstance, parent: EntityInstance,
+    position: SiblingPosition, keep_world: bool) -> Result<(), HierarchyError> {
+        use cgmath::Vector;
+
+        //Make sure we don't create a loop
+        if parent == child || (parent.is_valid() && self.is_ancestor_of(self.stamp(child), self.stamp(parent))) {
+            return Err(HierarchyError::Cycle);
+        }
+
+        //Check the position before touching anything
+        match position {
+            SiblingPosition::Last => { }
+            SiblingPosition::Index(index) => {
+                if !parent.is_valid() {
+                    return Err(HierarchyError::NoParent);
+                }
+                let count = self.iter_children(self.stamp(parent)).filter(|x| x.untyped() != child).count();
+                if index > count {
+                    return Err(HierarchyError::IndexOutOfRange);
+                }
+            }
+            SiblingPosition::Before(sibling) | SiblingPosition::After(sibling) => {
+                if !parent.is_valid() {
+                    return Err(HierarchyError::NoParent);
+                }
+                if sibling == child {
+                    return Ok(());
+                }
+            }
+        }
+
+        let idx = child.idx();
+        let old_parent = self.data.parents[idx];
+        let (world_pos, world_rot, world_scale) = self.get_world_transform(child);
+
+        //Remove from the old parent and siblings
+        self.unlink(child);
+
+        //Set the parent of the child
+        self.data.parents[idx] = parent;
+
+        //Link between the new siblings
+        if parent.is_valid() {
+            let next = match position {
+                SiblingPosition::Last => EntityInstance::none(),
+                SiblingPosition::Index(index) => {
+                    self.iter_children(self.stamp(parent)).nth(index)
+                        .map(|x| x.untyped()).unwrap_or(EntityInstance::none())
+                }
+                SiblingPosition::Before(sibling) => sibling,
+                SiblingPosition::After(sibling) => self.data.next_siblings[sibling.idx()],
+            };
+            let prev = if next.is_valid() {
+                self.data.prev_siblings[next.idx()]
+            }
+            else {
+                self.last_child(parent)
+            };
+
+            self.data.prev_siblings[idx] = prev;
+            self.data.next_siblings[idx] = next;
+            if prev.is_valid() {
+                self.data.next_siblings[prev.idx()] = child;
+            }
+            else {
+                self.data.first_children[parent.idx()] = child;
+            }
+            if next.is_valid() {
+                self.data.prev_siblings[next.idx()] = child;
+            }
+        }
+
+        self.changes.mark(&mut self.data.parent_ticks, idx);
+
+        //Reordering under the same parent doesn't move anything
+        if parent == old_parent && !keep_world {
+            return Ok(());
+        }
+        if parent != old_parent {
+            self.layout_dirty = true;
+        }
+
+        if keep_world {
+            //Solve for the local transform under the new parent
+            let (par_pos, par_rot, par_scale) = self.get_parent_world_transform(idx);
+            let inv_rot = par_rot.conjugate();
+            self.data.local_positions[idx] = inv_rot.mul_v(&(world_pos - par_pos)).div_v(&par_scale);
+            self.data.local_rotations[idx] = inv_rot.mul_q(&world_rot);
+            self.data.local_scales[idx] = world_scale.div_v(&par_scale);
+            self.local_changed(child);
+        }
+        else {
+            self.transform_changed(child);
+        }
+
+        Ok(())
+    }
+
+    /// Detaches an instance from its parent and siblings. Its children stay
+    /// attached.
+    fn unlink(&mut self, instance: EntityInstance) {
+        self.remove_instance(instance);
+
+        let idx = instance.idx();
+        self.data.parents[idx] = EntityInstance::none();
+        self.data.next_siblings[idx] = EntityInstance::none();
+        self.data.prev_siblings[idx] = EntityInstance::none();
+    }
+
+    pub fn get_first_child(&self, instance: TransformInstance) -> TransformInstance {
+        self.stamp(self.data.first_children[self.check(instance).idx()])
+    }
+
+    /// Returns the last child of an instance. This walks the sibling list.
+    pub fn get_last_child(&self, instance: TransformInstance) -> TransformInstance {
+        self.stamp(self.last_child(self.check(instance)))
+    }
+
+    fn last_child(&self, instance: EntityInstance) -> EntityInstance {
+        let mut last = EntityInstance::none();
+        let mut child = self.data.first_children[instance.idx()];
+        while child.is_valid() {
+            last = child;
+            child = self.data.next_siblings[child.idx()];
+        }
+        last
+    }
+
+    pub fn get_next_sibling(&self, instance: TransformInstance) -> TransformInstance {
+        self.stamp(self.data.next_siblings[self.check(instance).idx()])
+    }
+
+    pub fn get_prev_sibling(&self, instance: TransformInstance) -> TransformInstance {
+        self.stamp(self.data.prev_siblings[self.check(instance).idx()])
+    }
+
+
+
+    pub fn iter_children<'a>(&'a self, parent: TransformInstance) -> ChildIterator<'a> {
+        ChildIterator {
+            next_siblings: &self.data.next_siblings,
+            current: self.data.first_children[self.check(parent).idx()],
+            frame: self.frame,
+        }
+    }
+
+    pub fn get_children(&self, parent: TransformInstance) -> Vec<TransformInstance> {
+        self.iter_children(parent).collect()
+    }
+
+    pub fn get_child_entities(&self, parent: TransformInstance) -> Vec<Entity> {
+        self.iter_children(parent).map(|x| self.get_entity(x.untyped())).collect()
+    }
+
+    /// Iterates over the whole subtree below `root`, depth first. `root`
+    /// itself is not included.
+    pub fn iter_descendants<'a>(&'a self, root: TransformInstance) -> DescendantIterator<'a> {
+        let root = self.check(root);
+        DescendantIterator {
+            system: self,
+            root: root,
+            current: self.data.first_children[root.idx()],
+        }
+    }
+
+    /// Iterates over the whole subtree below `root`, one level at a time.
+    /// `root` itself is not included.
+    ///
+    /// This doesn't allocate, so it has to walk the tree again to find the
+    /// start of each level. Prefer iter_descendants() when order doesn't
+    /// matter.
+    pub fn iter_descendants_breadth_first<'a>(&'a self, root: TransformInstance)
+    -> BreadthFirstIterator<'a> {
+        let root = self.check(root);
+        BreadthFirstIterator {
+            system: self,
+            root: root,
+            current: self.data.first_children[root.idx()],
+            level: 1,
+        }
+    }
+
+    /// Iterates from the parent of `instance` up to its root.
+    pub fn iter_ancestors<'a>(&'a self, instance: TransformInstance) -> AncestorIterator<'a> {
+        AncestorIterator {
+            parents: &self.data.parents,
+            current: self.data.parents[self.check(instance).idx()],
+            frame: self.frame,
+        }
+    }
+
+    /// Iterates over every instance without a parent.
+    pub fn iter_roots<'a>(&'a self) -> RootIterator<'a> {
+        RootIterator {
+            parents: &self.data.parents,
+            index: 0,
+            frame: self.frame,
+        }
+    }
+
+    /// Returns the number of ancestors of an instance. Roots have depth 0.
+    pub fn depth(&self, instance: TransformInstance) -> usize {
+        self.iter_ancestors(instance).count()
+    }
+
+    /// Returns true if `ancestor` is the parent of `instance`, or the parent
+    /// of its parent, and so on.
+    pub fn is_ancestor_of(&self, ancestor: TransformInstance, instance: TransformInstance) -> bool {
+        self.iter_ancestors(instance).any(|x| x == ancestor)
+    }
+}
+
+
+
+impl System for TransformSystem {
+    fn name(&self) -> &'static str { "transform" }
+
+    fn load(&mut self, input: &mut Reader, entities: &[Entity]) {
+        TransformSystem::load(self, input, entities)
+    }
+
+    fn save(&self, output: &mut Writer) {
+        TransformSystem::save(self, output)
+    }
+
+    fn handle_destroyed(&mut self, entities: &[Entity], entity_manager: &mut EntityManager) {
+        TransformSystem::handle_destroyed(self, entities, entity_manager)
+    }
+
+    fn exists(&self, entity: Entity) -> bool {
+        TransformSystem::exists(self, entity)
+    }
+
+    fn count(&self) -> usize {
+        TransformSystem::count(self)
+    }
+
+    fn update(&mut self) {
+        TransformSystem::update(self)
+    }
+
+    fn as_any(&self) -> &Any { self }
+    fn as_any_mut(&mut self) -> &mut Any { self }
+}
+
+impl Join for TransformSystem {
+    type Instance = TransformInstance;
+
+    fn entities(&self) -> &[Entity] {
+        &self.data.entities
+    }
+
+    fn lookup(&self, entity: Entity) -> Option<TransformInstance> {
+        self.data.map.get(&entity).map(|x| self.stamp(*x))
+    }
+}
+
+
+
+/// Replaces every valid instance with its new index.
+fn remap(instances: &Vec<EntityInstance>, new_indices: &[EntityInstance]) -> Vec<EntityInstance> {
+    instances.iter().map(|inst| {
+        if inst.is_valid() { new_indices[inst.idx()] } else { *inst }
+    }).collect()
+}
+
+/// Applies a local transform on top of a parent's world transform.
+fn combine_transforms(
+par_pos: Vector3<f32>, par_rot: Quaternion<f32>, par_scale: Vector3<f32>,
+local_pos: Vector3<f32>, local_rot: Quaternion<f32>, local_scale: Vector3<f32>)
+-> (Vector3<f32>, Quaternion<f32>, Vector3<f32>) {
+    use cgmath::Vector;
+    (par_pos + par_rot.mul_v(&local_pos.mul_v(&par_scale)),
+    par_rot.mul_q(&local_rot),
+    par_scale.mul_v(&local_scale))
+}
+
+/// Builds the inverse of transform_matrix(), that is
+/// inverse(scale) * transpose(rotation) * inverse(translation).
+fn inverse_transform_matrix(position: Vector3<f32>, rotation: Quaternion<f32>, scale: Vector3<f32>)
+-> Matrix4<f32> {
+    use cgmath::Vector;
+    let inv_rot = rotation.conjugate();
+    let inv_scale = Vector3::new(1.0 / scale.x, 1.0 / scale.y, 1.0 / scale.z);
+    let (s, x, y, z) = (inv_rot.s, inv_rot.v.x, inv_rot.v.y, inv_rot.v.z);
+    let translation = inv_rot.mul_v(&(-position)).mul_v(&inv_scale);
+
+    //Columns of the inverse rotation matrix, each row scaled by its axis
+    Matrix4::new(
+        (1.0 - 2.0 * (y * y + z * z)) * inv_scale.x,
+        (2.0 * (x * y + s * z)) * inv_scale.y,
+        (2.0 * (x * z - s * y)) * inv_scale.z,
+        0.0,
+
+        (2.0 * (x * y - s * z)) * inv_scale.x,
+        (1.0 - 2.0 * (x * x + z * z)) * inv_scale.y,
+        (2.0 * (y * z + s * x)) * inv_scale.z,
+        0.0,
+
+        (2.0 * (x * z + s * y)) * inv_scale.x,
+        (2.0 * (y * z - s * x)) * inv_scale.y,
+        (1.0 - 2.0 * (x * x + y * y)) * inv_scale.z,
+        0.0,
+
+        translation.x, translation.y, translation.z, 1.0)
+}
+
+/// Builds a translation * rotation * scale matrix.
+fn transform_matrix(position: Vector3<f32>, rotation: Quaternion<f32>, scale: Vector3<f32>)
+-> Matrix4<f32> {
+    let (s, x, y, z) = (rotation.s, rotation.v.x, rotation.v.y, rotation.v.z);
+
+    //Columns of the rotation matrix, each scaled by its axis
+    Matrix4::new(
+        (1.0 - 2.0 * (y * y + z * z)) * scale.x,
+        (2.0 * (x * y + s * z)) * scale.x,
+        (2.0 * (x * z - s * y)) * scale.x,
+        0.0,
+
+        (2.0 * (x * y - s * z)) * scale.y,
+        (1.0 - 2.0 * (x * x + z * z)) * scale.y,
+        (2.0 * (y * z + s * x)) * scale.y,
+        0.0,
+
+        (2.0 * (x * z + s * y)) * scale.z,
+        (2.0 * (y * z - s * x)) * scale.z,
+        (1.0 - 2.0 * (x * x + y * y)) * scale.z,
+        0.0,
+
+        position.x, position.y, position.z, 1.0)
+}
+
+
+
+pub struct ChildIterator<'a> {
+    next_siblings: &'a Vec<EntityInstance>,
+    current: EntityInstance,
+    frame: u32,
+}
+
+impl<'a> Iterator for ChildIterator<'a> {
+    type Item = TransformInstance;
+    fn next(&mut self) -> Option<TransformInstance> {
+        if self.current.is_valid() {
+            let output = self.current;
+
+            //Advance to next sibling
+            self.current = self.next_siblings[output.idx()];
+
+            Some(Instance::new(output, self.frame))
+        }
+        else {
+            None
+        }
+    }
+}
+
+pub struct DescendantIterator<'a> {
+    system: &'a TransformSystem,
+    root: EntityInstance,
+    current: EntityInstance,
+}
+
+impl<'a> Iterator for DescendantIterator<'a> {
+    type Item = TransformInstance;
+    fn next(&mut self) -> Option<TransformInstance> {
+        if !self.current.is_valid() {
+            return None;
+        }
+        let output = self.current;
+
+        //Go down if we can, otherwise find the next sibling of the closest
+        //ancestor that has one
+        let first_child = self.system.data.first_children[output.idx()];
+        if first_child.is_valid() {
+            self.current = first_child;
+            return Some(self.system.stamp(output));
+        }
+
+        let mut current = output;
+        self.current = EntityInstance::none();
+        while current != self.root {
+            let next = self.system.data.next_siblings[current.idx()];
+            if next.is_valid() {
+                self.current = next;
+                break;
+            }
+            current = self.system.data.parents[current.idx()];
+        }
+
+        Some(self.system.stamp(output))
+    }
+}
+
+pub struct BreadthFirstIterator<'a> {
+    system: &'a TransformSystem,
+    root: EntityInstance,
+    current: EntityInstance,
+    //Depth of current below root
+    level: usize,
+}
+
+impl<'a> BreadthFirstIterator<'a> {
+    /// Finds the first instance at depth `level` below the root that comes
+    /// after `current` (at depth `depth`) in depth first order. If `descend`
+    /// is set, the subtree of `current` is searched as well.
+    fn seek(&self, mut current: EntityInstance, mut depth: usize, level: usize, descend: bool)
+    -> EntityInstance {
+        let sys = self.system;
+        let mut descend = descend;
+        loop {
+            if descend {
+                while depth < level {
+                    let child = sys.data.first_children[current.idx()];
+                    if !child.is_valid() {
+                        break;
+                    }
+                    current = child;
+                    depth += 1;
+                }
+                if depth == level {
+                    return current;
+                }
+            }
+            descend = true;
+
+            //Move on to the next sibling, climbing up when we run out
+            loop {
+                if current == self.root {
+                    return EntityInstance::none();
+                }
+                let next = sys.data.next_siblings[current.idx()];
+                if next.is_valid() {
+                    current = next;
+                    break;
+                }
+                current = sys.data.parents[current.idx()];
+                depth -= 1;
+            }
+            if depth == level {
+                return current;
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for BreadthFirstIterator<'a> {
+    type Item = TransformInstance;
+    fn next(&mut self) -> Option<TransformInstance> {
+        if !self.current.is_valid() {
+            return None;
+        }
+        let output = self.current;
+
+        //Next instance on this level, otherwise the first one on the next
+        self.current = self.seek(output, self.level, self.level, false);
+        if !self.current.is_valid() {
+            self.level += 1;
+            self.current = self.seek(self.root, 0, self.level, true);
+        }
+
+        Some(self.system.stamp(output))
+    }
+}
+
+pub struct AncestorIterator<'a> {
+    parents: &'a Vec<EntityInstance>,
+    current: EntityInstance,
+    frame: u32,
+}
+
+impl<'a> Iterator for AncestorIterator<'a> {
+    type Item = TransformInstance;
+    fn next(&mut self) -> Option<TransformInstance> {
+        if self.current.is_valid() {
+            let output = self.current;
+            self.current = self.parents[output.idx()];
+            Some(Instance::new(output, self.frame))
+        }
+        else {
+            None
+        }
+    }
+}
+
+pub struct RootIterator<'a> {
+    parents: &'a Vec<EntityInstance>,
+    index: usize,
+    frame: u32,
+}
+
+impl<'a> Iterator for RootIterator<'a> {
+    type Item = TransformInstance;
+    fn next(&mut self) -> Option<TransformInstance> {
+        while self.index < self.parents.len() {
+            let index = self.index;
+            self.index += 1;
+            if !self.parents[index].is_valid() {
+                return Some(Instance::new(EntityInstance::new(index as u32), self.frame));
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn iter_children_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let e1 = em.create();
+    let e2 = em.create();
+    let e3 = em.create();
+    let i1 = tr.create(e1);
+    let i2 = tr.create(e2);
+    let i3 = tr.create(e3);
+    tr.set_parent(i1, i2).unwrap();
+    tr.set_parent(i3, i2).unwrap();
+
+    let children: Vec<TransformInstance> = tr.iter_children(i2).collect();
+    assert_eq!(children, vec![i1, i3]);
+    let children: Vec<TransformInstance> = tr.iter_children(i1).collect();
+    assert_eq!(children, Vec::new());
+
+    for inst in tr.get_children(i2) {
+        tr.set_local_position(inst, Vector3::new(1.0, 2.0, 3.0));
+    }
+
+    assert_eq!(tr.get_child_entities(i2), vec![e1, e3]);
+}
+
+#[test]
+fn set_local_test() {
+    use cgmath::{ApproxEq, Rotation3};
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let e1 = em.create();
+    let e2 = em.create();
+    let i1 = tr.create(e1);
+    let i2 = tr.create(e2);
+
+    tr.set_parent(i2, i1).unwrap();
+    tr.set_local_position(i1, Vector3::new(1.0, 0.0, 1.0));
+    tr.set_local_rotation(i1, Rotation3::from_angle_y(::cgmath::Rad::turn_div_4()));
+    tr.set_local_position(i2, Vector3::new(0.0, 0.0, -1.0));
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(0.0, 0.0, 1.0)));
+
+    tr.set_local_scale(i1, Vector3::new(2.0, 2.0, 2.0));
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(-1.0, 0.0, 1.0)));
+}
+
+#[test]
+fn set_world_test() {
+    use cgmath::{ApproxEq, Rotation3};
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let e1 = em.create();
+    let e2 = em.create();
+    let e3 = em.create();
+    let i1 = tr.create(e1);
+    let i2 = tr.create(e2);
+    let i3 = tr.create(e3);
+
+    tr.set_parent(i2, i1).unwrap();
+    tr.set_parent(i3, i2).unwrap();
+    tr.set_local_position(i1, Vector3::new(1.0, 0.0, 1.0));
+    tr.set_local_rotation(i1, Rotation3::from_angle_y(::cgmath::Rad::turn_div_4()));
+    tr.set_local_scale(i1, Vector3::new(2.0, 2.0, 2.0));
+    tr.set_local_position(i3, Vector3::new(0.0, 1.0, 0.0));
+
+    //Local values are solved from the parent's world transform
+    tr.set_world_position(i2, Vector3::new(1.0, 0.0, -1.0));
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(1.0, 0.0, -1.0)));
+    assert!(tr.get_local_position(i2).approx_eq(&Vector3::new(1.0, 0.0, 0.0)));
+
+    //Children follow
+    assert!(tr.get_world_position(i3).approx_eq(&Vector3::new(1.0, 2.0, -1.0)));
+
+    tr.set_world_scale(i2, Vector3::new(1.0, 1.0, 1.0));
+    assert_eq!(tr.get_local_scale(i2), Vector3::new(0.5, 0.5, 0.5));
+    assert!(tr.get_world_position(i3).approx_eq(&Vector3::new(1.0, 1.0, -1.0)));
+
+    tr.set_world_rotation(i2, Quaternion::identity());
+    assert!(tr.get_world_rotation(i2).approx_eq(&Quaternion::identity()));
+    assert!(tr.get_world_rotation(i3).approx_eq(&Quaternion::identity()));
+}
+
+#[test]
+fn set_parent_test() {
+    use cgmath::{ApproxEq, Rotation3};
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let e1 = em.create();
+    let e2 = em.create();
+    let e3 = em.create();
+    let i1 = tr.create(e1);
+    let i2 = tr.create(e2);
+    let i3 = tr.create(e3);
+
+    tr.set_parent(i3, i1).unwrap();
+    tr.set_parent(i3, i2).unwrap();
+    assert_eq!(tr.get_children(i1), Vec::new());
+    assert_eq!(tr.get_children(i2), vec![i3]);
+    assert_eq!(tr.get_parent(i3), i2);
+
+    //Cycles are rejected
+    tr.set_parent(i2, i1).unwrap();
+    assert_eq!(tr.set_parent(i1, i3), Err(HierarchyError::Cycle));
+    assert_eq!(tr.set_parent(i1, i1), Err(HierarchyError::Cycle));
+    assert_eq!(tr.get_parent(i1), Instance::none());
+
+    //Detach to root
+    tr.set_parent(i3, Instance::none()).unwrap();
+    assert_eq!(tr.get_parent(i3), Instance::none());
+    assert_eq!(tr.get_children(i2), Vec::new());
+
+    //Keep the world transform when moving under a transformed parent
+    tr.set_local_position(i1, Vector3::new(1.0, 0.0, 1.0));
+    tr.set_local_rotation(i1, Rotation3::from_angle_y(::cgmath::Rad::turn_div_4()));
+    tr.set_local_scale(i1, Vector3::new(2.0, 2.0, 2.0));
+    tr.set_local_position(i3, Vector3::new(1.0, 0.0, -1.0));
+    tr.set_parent_keep_world(i3, i1).unwrap();
+    assert!(tr.get_world_position(i3).approx_eq(&Vector3::new(1.0, 0.0, -1.0)));
+    assert!(tr.get_local_position(i3).approx_eq(&Vector3::new(1.0, 0.0, 0.0)));
+    assert_eq!(tr.get_local_scale(i3), Vector3::new(0.5, 0.5, 0.5));
+
+    tr.set_parent_keep_world(i3, Instance::none()).unwrap();
+    assert!(tr.get_local_position(i3).approx_eq(&Vector3::new(1.0, 0.0, -1.0)));
+}
+
+#[test]
+fn sibling_order_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let p = tr.create(em.create());
+    let i1 = tr.create(em.create());
+    let i2 = tr.create(em.create());
+    let i3 = tr.create(em.create());
+    let i4 = tr.create(em.create());
+
+    tr.set_parent(i1, p).unwrap();
+    tr.set_parent(i2, p).unwrap();
+    tr.set_parent(i3, p).unwrap();
+    assert_eq!(tr.get_children(p), vec![i1, i2, i3]);
+    assert_eq!(tr.get_last_child(p), i3);
+
+    tr.insert_child_at(p, i4, 1).unwrap();
+    assert_eq!(tr.get_children(p), vec![i1, i4, i2, i3]);
+    assert_eq!(tr.insert_child_at(p, i4, 5), Err(HierarchyError::IndexOutOfRange));
+
+    tr.move_before(i3, i1).unwrap();
+    assert_eq!(tr.get_children(p), vec![i3, i1, i4, i2]);
+
+    tr.move_after(i3, i2).unwrap();
+    assert_eq!(tr.get_children(p), vec![i1, i4, i2, i3]);
+
+    tr.set_sibling_index(i1, 3).unwrap();
+    assert_eq!(tr.get_children(p), vec![i4, i2, i3, i1]);
+    assert_eq!(tr.get_sibling_index(i3), 2);
+
+    tr.set_sibling_index(i1, 0).unwrap();
+    assert_eq!(tr.get_children(p), vec![i1, i4, i2, i3]);
+    assert_eq!(tr.get_prev_sibling(i1), Instance::none());
+    assert_eq!(tr.get_next_sibling(i3), Instance::none());
+
+    assert_eq!(tr.set_sibling_index(p, 0), Err(HierarchyError::NoParent));
+}
+
+#[test]
+fn deferred_update_test() {
+    use cgmath::{ApproxEq, Rotation3};
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+    tr.set_update_mode(UpdateMode::Deferred);
+
+    let e1 = em.create();
+    let e2 = em.create();
+    let e3 = em.create();
+    let i1 = tr.create(e1);
+    let i2 = tr.create(e2);
+    let i3 = tr.create(e3);
+    tr.set_parent(i2, i1).unwrap();
+    tr.set_parent(i3, i2).unwrap();
+
+    tr.set_local_position(i1, Vector3::new(1.0, 0.0, 1.0));
+    tr.set_local_rotation(i1, Rotation3::from_angle_y(::cgmath::Rad::turn_div_4()));
+    tr.set_local_scale(i1, Vector3::new(2.0, 2.0, 2.0));
+    tr.set_local_position(i2, Vector3::new(0.0, 0.0, -1.0));
+    tr.set_local_position(i3, Vector3::new(0.0, 1.0, 0.0));
+
+    //Nothing has been propagated yet
+    assert_eq!(tr.data.world_positions[i3.idx()], Vector3::new(0.0, 0.0, 0.0));
+
+    //Reading early still gives the right answer
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(-1.0, 0.0, 1.0)));
+    assert!(tr.get_world_position(i3).approx_eq(&Vector3::new(-1.0, 2.0, 1.0)));
+
+    tr.update();
+    let (i1, i3) = (tr.get_instance(e1), tr.get_instance(e3));
+    assert!(tr.data.dirty.iter().all(|x| !*x));
+    assert!(tr.data.world_positions[i3.idx()].approx_eq(&Vector3::new(-1.0, 2.0, 1.0)));
+
+    //World setters solve against the up to date parent
+    tr.set_local_scale(i1, Vector3::new(1.0, 1.0, 1.0));
+    tr.set_world_position(i3, Vector3::new(0.0, 0.0, 0.0));
+    tr.update();
+    let (i2, i3) = (tr.get_instance(e2), tr.get_instance(e3));
+    assert!(tr.get_world_position(i3).approx_eq(&Vector3::new(0.0, 0.0, 0.0)));
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(0.0, 0.0, 1.0)));
+}
+
+#[test]
+fn sorted_layout_test() {
+    use cgmath::ApproxEq;
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+    tr.set_update_mode(UpdateMode::Deferred);
+
+    let e1 = em.create();
+    let e2 = em.create();
+    let e3 = em.create();
+    let e4 = em.create();
+    let i1 = tr.create(e1);
+    let i2 = tr.create(e2);
+    let i3 = tr.create(e3);
+    let i4 = tr.create(e4);
+
+    //e4 -> e1 -> e3, e4 -> e2
+    tr.set_parent(i3, i1).unwrap();
+    tr.set_parent(i1, i4).unwrap();
+    tr.set_parent(i2, i4).unwrap();
+    tr.set_local_position(i4, Vector3::new(1.0, 0.0, 0.0));
+    tr.set_local_position(i1, Vector3::new(0.0, 1.0, 0.0));
+    tr.set_local_position(i3, Vector3::new(0.0, 0.0, 1.0));
+
+    tr.set_layout(Layout::HierarchySorted);
+    tr.update();
+
+    assert_eq!(tr.data.entities, vec![e4, e1, e3, e2]);
+    for idx in 0..tr.count() {
+        let parent = tr.data.parents[idx];
+        assert!(!parent.is_valid() || parent.idx() < idx);
+    }
+
+    let i3 = tr.get_instance(e3);
+    assert!(tr.get_world_position(i3).approx_eq(&Vector3::new(1.0, 1.0, 1.0)));
+    assert_eq!(tr.get_child_entities(tr.get_instance(e4)), vec![e1, e2]);
+
+    //Reparenting restores the order on the next update
+    let i1 = tr.get_instance(e1);
+    let i2 = tr.get_instance(e2);
+    tr.set_parent(i1, i2).unwrap();
+    tr.set_local_position(i2, Vector3::new(0.0, 2.0, 0.0));
+    tr.update();
+    assert_eq!(tr.data.entities, vec![e4, e2, e1, e3]);
+    let i3 = tr.get_instance(e3);
+    assert!(tr.get_world_position(i3).approx_eq(&Vector3::new(1.0, 3.0, 1.0)));
+}
+
+#[test]
+#[should_panic]
+#[cfg(not(ndebug))]
+fn stale_instance_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    //Instances from before an update are caught in debug builds
+    let inst = tr.create(em.create());
+    tr.update();
+    tr.get_local_position(inst);
+}
+
+#[test]
+#[should_panic(expected = "TransformInstance from frame")]
+#[cfg(not(ndebug))]
+fn stale_after_sort_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+    tr.set_layout(Layout::HierarchySorted);
+
+    //The child comes first, so the edit makes update() re-sort
+    let child = tr.create(em.create());
+    let parent = tr.create(em.create());
+    tr.set_parent(child, parent).unwrap();
+    assert!(tr.is_sort_pending());
+    tr.update();
+    assert!(!tr.is_sort_pending());
+    tr.get_local_position(child);
+}
+
+#[test]
+#[should_panic(expected = "TransformInstance from frame")]
+#[cfg(not(ndebug))]
+fn stale_after_destroy_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    //Destroying moves the last instance into the hole, so every instance
+    //held across it is stale
+    let e1 = em.create();
+    let e2 = em.create();
+    tr.create(e1);
+    tr.create(e2);
+    let inst = tr.get_instance(e2);
+    tr.destroy(e1, &mut em);
+    tr.get_local_position(inst);
+}
+
+#[test]
+fn change_detection_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let e1 = em.create();
+    let e2 = em.create();
+    let e3 = em.create();
+    tr.create(e1);
+    tr.create(e2);
+    tr.create(e3);
+    let changed: Vec<Entity> = tr.iter_local_changed(tr.current_tick()).collect();
+    assert_eq!(changed, vec![e1, e2, e3]);
+
+    tr.update();
+    let since = tr.current_tick();
+    assert_eq!(tr.iter_world_changed(since).count(), 0);
+
+    //Moving a parent changes the world transform of the whole subtree
+    let (i1, i2) = (tr.get_instance(e1), tr.get_instance(e2));
+    tr.set_parent(i2, i1).unwrap();
+    tr.set_local_position(i1, Vector3::new(1.0, 0.0, 0.0));
+    let local: Vec<Entity> = tr.iter_local_changed(since).collect();
+    let world: Vec<Entity> = tr.iter_world_changed(since).collect();
+    let parent: Vec<Entity> = tr.iter_parent_changed(since).collect();
+    assert_eq!(local, vec![e1]);
+    assert_eq!(world, vec![e1, e2]);
+    assert_eq!(parent, vec![e2]);
+
+    //Deferred changes reach the world transforms in update()
+    tr.set_update_mode(UpdateMode::Deferred);
+    tr.update();
+    let since = tr.current_tick();
+    let i3 = tr.get_instance(e3);
+    tr.set_local_scale(i3, Vector3::new(2.0, 2.0, 2.0));
+    assert_eq!(tr.iter_world_changed(since).count(), 0);
+    tr.update();
+    let world: Vec<Entity> = tr.iter_world_changed(since).collect();
+    assert_eq!(world, vec![e3]);
+}
+
+#[test]
+fn world_matrix_test() {
+    use cgmath::{ApproxEq, Rotation3, Matrix, Vector4};
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let i1 = tr.create(em.create());
+    let i2 = tr.create(em.create());
+    tr.set_parent(i2, i1).unwrap();
+
+    tr.set_local_position(i1, Vector3::new(1.0, 0.0, 1.0));
+    tr.set_local_rotation(i1, Rotation3::from_angle_y(::cgmath::Rad::turn_div_4()));
+    tr.set_local_scale(i1, Vector3::new(1.0, 2.0, 3.0));
+    tr.set_local_position(i2, Vector3::new(1.0, 1.0, 1.0));
+
+    //Scale is applied per axis before the parent's rotation
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(4.0, 2.0, 0.0)));
+    assert!(tr.get_world_scale(i2).approx_eq(&Vector3::new(1.0, 2.0, 3.0)));
+
+    //The matrix agrees with the world transform
+    let origin = tr.get_world_matrix(i2).mul_v(&Vector4::new(0.0, 0.0, 0.0, 1.0));
+    assert!(origin.approx_eq(&Vector4::new(4.0, 2.0, 0.0, 1.0)));
+    let point = tr.get_world_matrix(i1).mul_v(&Vector4::new(1.0, 1.0, 1.0, 1.0));
+    assert!(point.approx_eq(&Vector4::new(4.0, 2.0, 0.0, 1.0)));
+
+    tr.set_world_scale(i2, Vector3::new(2.0, 2.0, 2.0));
+    assert!(tr.get_local_scale(i2).approx_eq(&Vector3::new(2.0, 1.0, 2.0 / 3.0)));
+}
+
+#[test]
+fn space_conversion_test() {
+    use cgmath::{ApproxEq, Rotation3, Matrix, Vector4};
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let i1 = tr.create(em.create());
+    let i2 = tr.create(em.create());
+    let i3 = tr.create(em.create());
+    tr.set_parent(i2, i1).unwrap();
+
+    tr.set_local_position(i1, Vector3::new(1.0, 0.0, 1.0));
+    tr.set_local_rotation(i1, Rotation3::from_angle_y(::cgmath::Rad::turn_div_4()));
+    tr.set_local_scale(i1, Vector3::new(1.0, 2.0, 3.0));
+    tr.set_local_position(i2, Vector3::new(1.0, 1.0, 1.0));
+    tr.set_local_position(i3, Vector3::new(0.0, 5.0, 0.0));
+
+    let point = Vector3::new(1.0, 1.0, 1.0);
+    assert!(tr.transform_point(i1, point).approx_eq(&Vector3::new(4.0, 2.0, 0.0)));
+    assert!(tr.transform_vector(i1, point).approx_eq(&Vector3::new(3.0, 2.0, -1.0)));
+    assert!(tr.transform_direction(i1, point).approx_eq(&Vector3::new(1.0, 1.0, -1.0)));
+
+    assert!(tr.inverse_transform_point(i1, Vector3::new(4.0, 2.0, 0.0)).approx_eq(&point));
+    assert!(tr.inverse_transform_vector(i1, Vector3::new(3.0, 2.0, -1.0)).approx_eq(&point));
+    assert!(tr.inverse_transform_direction(i1, Vector3::new(1.0, 1.0, -1.0)).approx_eq(&point));
+
+    //From i2's origin to i3's space
+    let matrix = tr.local_to_local(i2, i3);
+    let origin = matrix.mul_v(&Vector4::new(0.0, 0.0, 0.0, 1.0));
+    assert!(origin.approx_eq(&Vector4::new(4.0, -3.0, 0.0, 1.0)));
+
+    //Round trip through world space
+    let there = tr.local_to_local(i1, Instance::none());
+    let back = tr.local_to_local(Instance::none(), i1);
+    let round_trip = back.mul_m(&there).mul_v(&Vector4::new(1.0, 2.0, 3.0, 1.0));
+    assert!(round_trip.approx_eq(&Vector4::new(1.0, 2.0, 3.0, 1.0)));
+}
+
+#[test]
+fn manipulation_test() {
+    use cgmath::{ApproxEq, Rotation3};
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let i1 = tr.create(em.create());
+    let i2 = tr.create(em.create());
+    tr.set_parent(i2, i1).unwrap();
+    tr.set_local_position(i1, Vector3::new(1.0, 0.0, 1.0));
+    tr.set_local_rotation(i1, Rotation3::from_angle_y(::cgmath::Rad::turn_div_4()));
+    tr.set_local_scale(i1, Vector3::new(2.0, 2.0, 2.0));
+
+    //Local translation follows the instance's axes, world ignores them
+    tr.translate(i2, Vector3::new(1.0, 0.0, 0.0), Space::Local);
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(1.0, 0.0, 0.0)));
+    tr.translate(i2, Vector3::new(1.0, 0.0, 0.0), Space::World);
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(2.0, 0.0, 0.0)));
+    assert!(tr.get_local_position(i2).approx_eq(&Vector3::new(0.5, 0.0, 0.5)));
+
+    let quarter_x: Quaternion<f32> = Rotation3::from_angle_x(::cgmath::Rad::turn_div_4());
+    tr.rotate(i2, quarter_x, Space::World);
+    assert!(tr.transform_direction(i2, Vector3::new(0.0, 0.0, 1.0))
+        .approx_eq(&Vector3::new(1.0, 0.0, 0.0)));
+    tr.rotate(i2, quarter_x, Space::Local);
+    assert!(tr.transform_direction(i2, Vector3::new(0.0, 0.0, 1.0))
+        .approx_eq(&Vector3::new(0.0, 0.0, -1.0)));
+
+    //Off the axes, so an inverted rotation can't pass
+    let half = 0.70710677;
+    tr.look_at(i2, Vector3::new(3.0, 1.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+    assert!(tr.transform_direction(i2, Vector3::new(0.0, 0.0, 1.0))
+        .approx_eq(&Vector3::new(half, half, 0.0)));
+    assert!(tr.transform_direction(i2, Vector3::new(0.0, 1.0, 0.0))
+        .approx_eq(&Vector3::new(-half, half, 0.0)));
+
+    //Straight up or down along `up` still turns +Z onto the target
+    tr.look_at(i2, Vector3::new(2.0, -5.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+    assert!(tr.transform_direction(i2, Vector3::new(0.0, 0.0, 1.0))
+        .approx_eq(&Vector3::new(0.0, -1.0, 0.0)));
+    tr.look_at(i2, Vector3::new(2.0, 3.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+    assert!(tr.transform_direction(i2, Vector3::new(0.0, 0.0, 1.0))
+        .approx_eq(&Vector3::new(0.0, 1.0, 0.0)));
+
+    tr.look_at(i2, Vector3::new(2.0, 0.0, 5.0), Vector3::new(0.0, 1.0, 0.0));
+    assert!(tr.transform_direction(i2, Vector3::new(0.0, 0.0, 1.0))
+        .approx_eq(&Vector3::new(0.0, 0.0, 1.0)));
+    assert!(tr.transform_direction(i2, Vector3::new(0.0, 1.0, 0.0))
+        .approx_eq(&Vector3::new(0.0, 1.0, 0.0)));
+
+    tr.rotate_around(i2, Vector3::new(2.0, 0.0, 1.0), Vector3::new(0.0, 1.0, 0.0),
+        ::cgmath::Rad::turn_div_2());
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(2.0, 0.0, 2.0)));
+    assert!(tr.transform_direction(i2, Vector3::new(0.0, 0.0, 1.0))
+        .approx_eq(&Vector3::new(0.0, 0.0, -1.0)));
+}
+
+#[test]
+fn traversal_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    //    i0        i7
+    //   /  \
+    //  i1   i2
+    //  |   /  \
+    //  i3 i4  i5
+    //          |
+    //          i6
+    let i: Vec<TransformInstance> = (0..8).map(|_| tr.create(em.create())).collect();
+    tr.set_parent(i[1], i[0]).unwrap();
+    tr.set_parent(i[2], i[0]).unwrap();
+    tr.set_parent(i[3], i[1]).unwrap();
+    tr.set_parent(i[4], i[2]).unwrap();
+    tr.set_parent(i[5], i[2]).unwrap();
+    tr.set_parent(i[6], i[5]).unwrap();
+
+    let depth_first: Vec<TransformInstance> = tr.iter_descendants(i[0]).collect();
+    assert_eq!(depth_first, vec![i[1], i[3], i[2], i[4], i[5], i[6]]);
+    let depth_first: Vec<TransformInstance> = tr.iter_descendants(i[2]).collect();
+    assert_eq!(depth_first, vec![i[4], i[5], i[6]]);
+    assert_eq!(tr.iter_descendants(i[7]).count(), 0);
+
+    let breadth_first: Vec<TransformInstance> = tr.iter_descendants_breadth_first(i[0]).collect();
+    assert_eq!(breadth_first, vec![i[1], i[2], i[3], i[4], i[5], i[6]]);
+    let breadth_first: Vec<TransformInstance> = tr.iter_descendants_breadth_first(i[1]).collect();
+    assert_eq!(breadth_first, vec![i[3]]);
+
+    let ancestors: Vec<TransformInstance> = tr.iter_ancestors(i[6]).collect();
+    assert_eq!(ancestors, vec![i[5], i[2], i[0]]);
+
+    let roots: Vec<TransformInstance> = tr.iter_roots().collect();
+    assert_eq!(roots, vec![i[0], i[7]]);
+
+    assert_eq!(tr.depth(i[0]), 0);
+    assert_eq!(tr.depth(i[6]), 3);
+    assert!(tr.is_ancestor_of(i[0], i[6]));
+    assert!(tr.is_ancestor_of(i[2], i[4]));
+    assert!(!tr.is_ancestor_of(i[1], i[4]));
+    assert!(!tr.is_ancestor_of(i[6], i[6]));
+}
+
+#[test]
+fn validate_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let i1 = tr.create(em.create());
+    let i2 = tr.create(em.create());
+    let i3 = tr.create(em.create());
+    tr.set_parent(i2, i1).unwrap();
+    tr.set_parent(i3, i1).unwrap();
+    assert_eq!(tr.validate(), Ok(()));
+
+    //Break a back-pointer and a parent link by hand
+    tr.data.prev_siblings[i3.idx()] = EntityInstance::none();
+    tr.data.parents[i1.idx()] = i2.untyped();
+    let errors = tr.validate().err().unwrap();
+    assert!(errors.iter().any(|x| x.instance == i2.untyped() && x.message.contains("next sibling")));
+    assert!(errors.iter().any(|x| x.message.contains("cycle")));
+
+    //Two siblings linked into a ring of their own, consistent in both
+    //directions but cut off from the parent
+    let mut tr = TransformSystem::new();
+    let p = tr.create(em.create());
+    let c1 = tr.create(em.create());
+    let c2 = tr.create(em.create());
+    let c3 = tr.create(em.create());
+    for child in [c1, c2, c3].iter() {
+        tr.set_parent(*child, p).unwrap();
+    }
+    tr.data.next_siblings[c1.idx()] = EntityInstance::none();
+    tr.data.prev_siblings[c2.idx()] = c3.untyped();
+    tr.data.next_siblings[c3.idx()] = c2.untyped();
+    let errors = tr.validate().err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].instance == p.untyped() && errors[0].message.contains("reaches 1 of the 3"));
+}
+
+#[test]
+fn load_append_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let e1 = em.create();
+    let e2 = em.create();
+    let i1 = tr.create(e1);
+    let i2 = tr.create(e2);
+    tr.set_parent(i2, i1).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    tr.save(&mut output);
+
+    //Loading into a system that already has rows keeps both hierarchies
+    let e3 = em.create();
+    let e4 = em.create();
+    tr.load(&mut &output[..], &[e3, e4]);
+    assert_eq!(tr.count(), 4);
+    assert_eq!(tr.validate(), Ok(()));
+    let i3 = tr.get_instance(e3);
+    let i4 = tr.get_instance(e4);
+    assert_eq!(tr.get_parent(i4), i3);
+    assert_eq!(tr.get_child_entities(i3), vec![e4]);
+    assert_eq!(tr.get_child_entities(tr.get_instance(e1)), vec![e2]);
+}
+
+#[test]
+fn destroy_reparented_test() {
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    let e: Vec<Entity> = (0..5).map(|_| em.create()).collect();
+    let i: Vec<TransformInstance> = e.iter().map(|x| tr.create(*x)).collect();
+    tr.set_parent(i[1], i[0]).unwrap();
+    tr.set_parent(i[4], i[0]).unwrap();
+    tr.set_parent(i[2], i[4]).unwrap();
+    tr.set_parent(i[4], i[3]).unwrap();
+
+    //Destroy the last instance while it has a previous sibling
+    tr.set_parent(i[4], i[0]).unwrap();
+    tr.destroy(e[4], &mut em);
+    assert_eq!(tr.validate(), Ok(()));
+    assert!(!tr.exists(e[2]));
+    assert_eq!(tr.get_child_entities(tr.get_instance(e[0])), vec![e[1]]);
+}
+
+/// Small xorshift generator so the randomized tests are repeatable.
+#[cfg(test)]
+struct TestRng(u32);
+
+#[cfg(test)]
+impl TestRng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as usize % n
+    }
+}
+
+#[test]
+fn random_hierarchy_test() {
+    use cgmath::ApproxEq;
+    let mut rng = TestRng(0x9E3779B9);
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+    let mut alive: Vec<Entity> = Vec::new();
+
+    for step in 0..5000 {
+        let op = rng.below(10);
+        if alive.len() < 2 || op < 3 {
+            let en = em.create();
+            let inst = tr.create(en);
+            tr.set_local_position(inst, Vector3::new(rng.below(10) as f32, 0.0, 0.0));
+            alive.push(en);
+        }
+        else if op < 4 {
+            let policy = match rng.below(3) {
+                0 => DestroyPolicy::Cascade,
+                1 => DestroyPolicy::Orphan,
+                _ => DestroyPolicy::ReparentToGrandparent,
+            };
+            tr.set_destroy_policy(policy);
+
+            let en = alive[rng.below(alive.len())];
+            tr.destroy(en, &mut em);
+            alive.retain(|x| tr.exists(*x));
+            assert!(alive.iter().all(|x| em.alive(*x)));
+        }
+        else if op < 8 {
+            let child = tr.get_instance(alive[rng.below(alive.len())]);
+            let parent = if rng.below(5) == 0 {
+                Instance::none()
+            }
+            else {
+                tr.get_instance(alive[rng.below(alive.len())])
+            };
+
+            let before = tr.get_world_position(child);
+            let cycle = parent == child || (parent.is_valid() && tr.is_ancestor_of(child, parent));
+            let keep_world = op == 7;
+            let result = if keep_world {
+                tr.set_parent_keep_world(child, parent)
+            }
+            else {
+                tr.set_parent(child, parent)
+            };
+            assert_eq!(result.is_err(), cycle);
+            if keep_world {
+                assert!(tr.get_world_position(child).approx_eq(&before));
+            }
+        }
+        else if op < 9 {
+            let inst = tr.get_instance(alive[rng.below(alive.len())]);
+            let sibling = tr.get_instance(alive[rng.below(alive.len())]);
+            let _ = tr.move_before(inst, sibling);
+        }
+        else {
+            let layout = if rng.below(2) == 0 { Layout::Unordered } else { Layout::HierarchySorted };
+            tr.set_layout(layout);
+            tr.update();
+        }
+
+        assert!(tr.validate().is_ok(), "step {}: {:?}", step, tr.validate());
+        assert_eq!(tr.count(), alive.len());
+    }
+}
+
+#[test]
+fn destroy_policy_test() {
+    use cgmath::ApproxEq;
+    let mut em = EntityManager::new();
+    let mut tr = TransformSystem::new();
+
+    //e0 -> e1 -> (e2, e3), e2 -> e4
+    fn build(tr: &mut TransformSystem, e: &[Entity]) {
+        let i: Vec<TransformInstance> = e.iter().map(|x| tr.create(*x)).collect();
+        tr.set_parent(i[1], i[0]).unwrap();
+        tr.set_parent(i[2], i[1]).unwrap();
+        tr.set_parent(i[3], i[1]).unwrap();
+        tr.set_parent(i[4], i[2]).unwrap();
+        tr.set_local_position(i[1], Vector3::new(0.0, 1.0, 0.0));
+        tr.set_local_position(i[2], Vector3::new(1.0, 0.0, 0.0));
+    }
+
+    //Cascade destroys the descendant entities as well
+    let e: Vec<Entity> = (0..5).map(|_| em.create()).collect();
+    build(&mut tr, &e);
+    tr.destroy(e[1], &mut em);
+    assert_eq!(tr.count(), 1);
+    assert!(em.alive(e[1]));
+    assert!(!em.alive(e[2]) && !em.alive(e[3]) && !em.alive(e[4]));
+    assert_eq!(em.poll_destroyed().len(), 3);
+    tr.destroy(e[0], &mut em);
+
+    //Orphans keep their world transform
+    let e: Vec<Entity> = (0..5).map(|_| em.create()).collect();
+    tr.set_destroy_policy(DestroyPolicy::Orphan);
+    build(&mut tr, &e);
+    tr.destroy(e[1], &mut em);
+    assert_eq!(tr.count(), 4);
+    let i2 = tr.get_instance(e[2]);
+    assert_eq!(tr.get_parent(i2), Instance::none());
+    assert!(tr.get_world_position(i2).approx_eq(&Vector3::new(1.0, 1.0, 0.0)));
+    assert_eq!(tr.get_child_entities(i2), vec![e[4]]);
+    for en in [e[0], e[2], e[3], e[4]].iter() {
+        tr.destroy(*en, &mut em);
+    }
+    assert_eq!(tr.count(), 0);
+
+    //Reparented children take the destroyed instance's place
+    tr.set_destroy_policy(DestroyPolicy::ReparentToGrandparent);
+    build(&mut tr, &e);
+    let extra = em.create();
+    let i_extra = tr.create(extra);
+    let i0 = tr.get_instance(e[0]);
+    tr.set_parent(i_extra, i0).unwrap();
+    tr.destroy(e[1], &mut em);
+    let i0 = tr.get_instance(e[0]);
+    assert_eq!(tr.get_child_entities(i0), vec![e[2], e[3], extra]);
+    assert!(tr.get_world_position(tr.get_instance(e[2])).approx_eq(&Vector3::new(1.0, 1.0, 0.0)));
+    assert!(tr.validate().is_ok());
+}